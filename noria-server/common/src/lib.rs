@@ -8,7 +8,7 @@ mod records;
 pub use self::local::*;
 pub use self::map::*;
 pub use self::records::*;
-pub use noria::DataType;
+pub use noria::{DataType, TypedColumns};
 
 pub trait SizeOf {
     fn deep_size_of(&self) -> u64;
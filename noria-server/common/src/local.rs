@@ -126,6 +126,8 @@ pub enum KeyType<'a> {
     Quad((DataType, DataType, DataType, DataType)),
     Quin((DataType, DataType, DataType, DataType, DataType)),
     Sex((DataType, DataType, DataType, DataType, DataType, DataType)),
+    /// A key of more than six columns.
+    Multi(Vec<DataType>),
 }
 
 impl<'a> KeyType<'a> {
@@ -136,6 +138,9 @@ impl<'a> KeyType<'a> {
     {
         let mut other = other.into_iter();
         let len = other.len();
+        if len > 6 {
+            return KeyType::Multi(other.cloned().collect());
+        }
         let mut more = move || other.next().unwrap();
         match len {
             0 => unreachable!(),
@@ -163,7 +168,7 @@ impl<'a> KeyType<'a> {
                 more().clone(),
                 more().clone(),
             )),
-            _ => unimplemented!(),
+            _ => unreachable!(),
         }
     }
 }
@@ -1818,9 +1818,10 @@ impl Domain {
                             // we'll just end up with the same "need replay" response that
                             // triggered this replay initially.
                             if let Some(state) = self.state.get_mut(segment.node) {
-                                for key in backfill_keys.iter() {
-                                    state.mark_filled(key.clone(), tag);
-                                }
+                                state.mark_filled_batch(
+                                    backfill_keys.iter().cloned().collect(),
+                                    tag,
+                                );
                             } else {
                                 n.with_reader_mut(|r| {
                                     // we must be filling a hole in a Reader. we need to ensure
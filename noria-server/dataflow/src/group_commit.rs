@@ -101,7 +101,9 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data, tracer } = unsafe { inner.take() };
+                    let Input {
+                        dst, data, tracer, ..
+                    } = unsafe { inner.take() };
 
                     assert_eq!(senders.len(), 0);
                     assert_eq!(merged_dst, dst);
@@ -137,6 +139,7 @@ impl GroupCommitQueueSet {
                 dst: merged_dst,
                 data: merged_data,
                 tracer: merged_tracer,
+                compress_above: None,
             }),
             src: None,
             senders: all_senders,
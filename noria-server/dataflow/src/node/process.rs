@@ -33,7 +33,9 @@ impl Node {
                     Some(Packet::Input {
                         inner, mut senders, ..
                     }) => {
-                        let Input { dst, data, tracer } = unsafe { inner.take() };
+                        let Input {
+                            dst, data, tracer, ..
+                        } = unsafe { inner.take() };
                         let mut rs = b.process(addr, data, &*state);
 
                         // When a replay originates at a base node, we replay the data *through* that
@@ -2,7 +2,7 @@ use crate::prelude::*;
 use noria::{Modification, Operation, TableOperation};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use vec_map::VecMap;
 
 /// Base is used to represent the root nodes of the Noria data flow graph.
@@ -17,8 +17,15 @@ pub struct Base {
     defaults: Vec<DataType>,
     dropped: Vec<usize>,
     unmodified: bool,
+
+    idempotency_window: usize,
+    recent_tokens: VecDeque<u64>,
+    seen_tokens: HashSet<u64>,
 }
 
+/// The number of idempotent insert tokens a base remembers by default.
+const DEFAULT_IDEMPOTENCY_WINDOW: usize = 1024;
+
 impl Base {
     /// Create a non-durable base node operator.
     pub fn new(defaults: Vec<DataType>) -> Self {
@@ -33,6 +40,15 @@ impl Base {
         self
     }
 
+    /// Builder that sets how many of the most recent idempotent insert tokens are remembered.
+    ///
+    /// An idempotent insert is ignored if its token is among the ones remembered. Note that the
+    /// tokens are not persisted, and are forgotten if the base's domain restarts.
+    pub fn with_idempotency_window(mut self, window: usize) -> Base {
+        self.idempotency_window = window;
+        self
+    }
+
     pub fn key(&self) -> Option<&[usize]> {
         self.primary_key.as_ref().map(|cols| &cols[..])
     }
@@ -95,6 +111,10 @@ impl Clone for Base {
             defaults: self.defaults.clone(),
             dropped: self.dropped.clone(),
             unmodified: self.unmodified,
+
+            idempotency_window: self.idempotency_window,
+            recent_tokens: self.recent_tokens.clone(),
+            seen_tokens: self.seen_tokens.clone(),
         }
     }
 }
@@ -107,6 +127,10 @@ impl Default for Base {
             defaults: Vec::new(),
             dropped: Vec::new(),
             unmodified: true,
+
+            idempotency_window: DEFAULT_IDEMPOTENCY_WINDOW,
+            recent_tokens: VecDeque::new(),
+            seen_tokens: HashSet::new(),
         }
     }
 }
@@ -115,8 +139,11 @@ fn key_val(i: usize, col: usize, r: &TableOperation) -> &DataType {
     match *r {
         TableOperation::Insert(ref row) => &row[col],
         TableOperation::Delete { ref key } => &key[i],
+        TableOperation::ConditionalDelete { ref key, .. } => &key[i],
         TableOperation::Update { ref key, .. } => &key[i],
         TableOperation::InsertOrUpdate { ref row, .. } => &row[col],
+        TableOperation::IdempotentInsert { ref row, .. } => &row[col],
+        TableOperation::InsertIfAbsent { ref row } => &row[col],
     }
 }
 
@@ -127,17 +154,59 @@ fn key_of<'a>(key_cols: &'a [usize], r: &'a TableOperation) -> impl Iterator<Ite
         .map(move |(i, col)| key_val(i, *col, r))
 }
 
+fn apply(op: Operation, old: &DataType, v: DataType) -> DataType {
+    let old: i128 = old.clone().into();
+    let delta: i128 = v.into();
+    match op {
+        Operation::Add => (old + delta).into(),
+        Operation::Sub => (old - delta).into(),
+    }
+}
+
 impl Base {
     pub(in crate::node) fn take(&mut self) -> Self {
         Clone::clone(self)
     }
 
+    /// Record that an idempotent insert with the given token is being applied, returning false if
+    /// the token has been seen recently.
+    fn first_use_of(&mut self, token: u64) -> bool {
+        if self.seen_tokens.contains(&token) {
+            return false;
+        }
+
+        if self.idempotency_window != 0 {
+            self.seen_tokens.insert(token);
+            self.recent_tokens.push_back(token);
+            if self.recent_tokens.len() > self.idempotency_window {
+                let oldest = self.recent_tokens.pop_front().unwrap();
+                self.seen_tokens.remove(&oldest);
+            }
+        }
+        true
+    }
+
     pub(in crate::node) fn process(
         &mut self,
         us: LocalNodeIndex,
-        mut ops: Vec<TableOperation>,
+        ops: Vec<TableOperation>,
         state: &StateMap,
     ) -> Records {
+        // drop idempotent inserts we have already applied; the rest are just inserts
+        let mut ops: Vec<_> = ops
+            .into_iter()
+            .filter_map(|op| match op {
+                TableOperation::IdempotentInsert { row, token } => {
+                    if self.first_use_of(token) {
+                        Some(TableOperation::Insert(row))
+                    } else {
+                        None
+                    }
+                }
+                op => Some(op),
+            })
+            .collect();
+
         if self.primary_key.is_none() || ops.is_empty() {
             return ops
                 .into_iter()
@@ -199,7 +268,7 @@ impl Base {
                 was = current.clone();
             }
 
-            let update = match op {
+            let (update, inserted) = match op {
                 TableOperation::Insert(row) => {
                     if let Some(ref was) = was {
                         eprintln!("base ignoring {:?} since it already has {:?}", row, was);
@@ -218,13 +287,32 @@ impl Base {
                     }
                     continue;
                 }
-                TableOperation::Update { set, .. } => set,
+                TableOperation::ConditionalDelete { expect, .. } => {
+                    let matches = current
+                        .as_ref()
+                        .map(|r| expect.iter().all(|(col, v)| r.get(*col) == Some(v)))
+                        .unwrap_or(false);
+                    if matches {
+                        current = None;
+                    }
+                    continue;
+                }
+                TableOperation::InsertIfAbsent { row } => {
+                    // unlike a plain insert, this also takes earlier operations in the batch into
+                    // account, and is not worth a warning when it is dropped
+                    if current.is_none() {
+                        current = Some(Cow::Owned(row));
+                    }
+                    continue;
+                }
+                TableOperation::IdempotentInsert { .. } => unreachable!(),
+                TableOperation::Update { set, .. } => (set, None),
                 TableOperation::InsertOrUpdate { row, update } => {
                     if current.is_none() {
                         current = Some(Cow::Owned(row));
                         continue;
                     }
-                    update
+                    (update, Some(row))
                 }
             };
 
@@ -234,18 +322,26 @@ impl Base {
                 continue;
             }
 
+            // `Table` rejects modifications that read from a missing inserted row or column, but
+            // this is client input, so skip any that slip through rather than panic the domain
+            let inserted_col =
+                |col: usize| -> Option<DataType> { inserted.as_ref()?.get(col).cloned() };
+
             let mut future = current.unwrap().into_owned();
             for (col, op) in update.into_iter().enumerate() {
                 // XXX: make sure user doesn't update primary key?
                 match op {
                     Modification::Set(v) => future[col] = v,
-                    Modification::Apply(op, v) => {
-                        let old: i128 = future[col].clone().into();
-                        let delta: i128 = v.into();
-                        future[col] = match op {
-                            Operation::Add => (old + delta).into(),
-                            Operation::Sub => (old - delta).into(),
-                        };
+                    Modification::Apply(op, v) => future[col] = apply(op, &future[col], v),
+                    Modification::FromInserted(from) => {
+                        if let Some(v) = inserted_col(from) {
+                            future[col] = v;
+                        }
+                    }
+                    Modification::ApplyInserted(op, from) => {
+                        if let Some(v) = inserted_col(from) {
+                            future[col] = apply(op, &future[col], v);
+                        }
                     }
                     Modification::None => {}
                 }
@@ -401,6 +497,160 @@ mod tests {
         );
     }
 
+    /// Returns a closure that processes a batch of operations on a base keyed on its first
+    /// column, and materializes the result in memory.
+    fn keyed_base() -> impl FnMut(Vec<TableOperation>) -> Records {
+        let mut state: Box<dyn State> = Box::new(MemoryState::default());
+        state.add_key(&[0], None);
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut states = StateMap::new();
+        states.insert(local, state);
+
+        let mut b = Base::new(vec![]).with_key(vec![0]);
+        move |u: Vec<TableOperation>| {
+            let mut m = b.process(local, u, &states);
+            crate::node::materialize(&mut m, None, states.get_mut(local));
+            m
+        }
+    }
+
+    #[test]
+    fn insert_or_update_from_inserted() {
+        let mut one = keyed_base();
+
+        let records = |rs: Vec<(Vec<DataType>, bool)>| -> Records { rs.into() };
+        let upsert = |n: i32| TableOperation::InsertOrUpdate {
+            row: vec![1.into(), n.into()],
+            update: vec![
+                Modification::None,
+                Modification::ApplyInserted(Operation::Add, 1),
+            ],
+        };
+
+        assert_eq!(
+            one(vec![upsert(2)]),
+            records(vec![(vec![1.into(), 2.into()], true)])
+        );
+        assert_eq!(
+            one(vec![upsert(3)]),
+            records(vec![
+                (vec![1.into(), 2.into()], false),
+                (vec![1.into(), 5.into()], true),
+            ])
+        );
+        assert_eq!(
+            one(vec![TableOperation::InsertOrUpdate {
+                row: vec![1.into(), 7.into()],
+                update: vec![Modification::None, Modification::FromInserted(1)],
+            }]),
+            records(vec![
+                (vec![1.into(), 5.into()], false),
+                (vec![1.into(), 7.into()], true),
+            ])
+        );
+    }
+
+    #[test]
+    fn inserted_modification_without_inserted_row() {
+        let mut one = keyed_base();
+
+        one(vec![TableOperation::Insert(vec![1.into(), 2.into()])]);
+
+        // an update has no inserted row to read from
+        let update = TableOperation::Update {
+            key: vec![1.into()],
+            set: vec![Modification::None, Modification::FromInserted(0)],
+        };
+        assert_eq!(one(vec![update]), Records::default());
+
+        // and an insert-or-update only has the columns of its row
+        let upsert = TableOperation::InsertOrUpdate {
+            row: vec![1.into(), 3.into()],
+            update: vec![
+                Modification::None,
+                Modification::ApplyInserted(Operation::Add, 2),
+            ],
+        };
+        assert_eq!(one(vec![upsert]), Records::default());
+    }
+
+    #[test]
+    fn conditional_delete() {
+        let mut one = keyed_base();
+
+        let delete_if = |v: &str| TableOperation::ConditionalDelete {
+            key: vec![1.into()],
+            expect: vec![(1, v.into())],
+        };
+
+        one(vec![TableOperation::Insert(vec![1.into(), "a".into()])]);
+        assert_eq!(one(vec![delete_if("b")]), Records::default());
+
+        // a column the row doesn't have never matches
+        let out_of_range = TableOperation::ConditionalDelete {
+            key: vec![1.into()],
+            expect: vec![(2, "a".into())],
+        };
+        assert_eq!(one(vec![out_of_range]), Records::default());
+
+        let deleted: Records = Record::Negative(vec![1.into(), "a".into()]).into();
+        assert_eq!(one(vec![delete_if("a")]), deleted);
+    }
+
+    #[test]
+    fn insert_if_absent() {
+        let mut one = keyed_base();
+
+        let insert = |v: &str| TableOperation::InsertIfAbsent {
+            row: vec![1.into(), v.into()],
+        };
+
+        let inserted: Records = Record::Positive(vec![1.into(), "a".into()]).into();
+        assert_eq!(one(vec![insert("a"), insert("b")]), inserted);
+        assert_eq!(one(vec![insert("c")]), Records::default());
+
+        // a row deleted earlier in the same batch no longer counts as present
+        let replaced: Records = vec![
+            (vec![1.into(), "a".into()], false),
+            (vec![1.into(), "d".into()], true),
+        ]
+        .into();
+        assert_eq!(
+            one(vec![
+                TableOperation::Delete {
+                    key: vec![1.into()]
+                },
+                insert("d"),
+            ]),
+            replaced
+        );
+    }
+
+    #[test]
+    fn idempotent_insert() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let states = StateMap::new();
+        let mut b = Base::new(vec![]).with_idempotency_window(1);
+        let insert = |v: i32, token: u64| TableOperation::IdempotentInsert {
+            row: vec![v.into()],
+            token,
+        };
+
+        let inserted: Records = vec![Record::Positive(vec![1.into()])].into();
+        assert_eq!(
+            b.process(local, vec![insert(1, 1), insert(1, 1)], &states),
+            inserted
+        );
+        assert_eq!(
+            b.process(local, vec![insert(1, 1)], &states),
+            Records::default()
+        );
+
+        // token 1 falls out of the window once token 2 is seen
+        b.process(local, vec![insert(2, 2)], &states);
+        assert_eq!(b.process(local, vec![insert(1, 1)], &states), inserted);
+    }
+
     #[test]
     fn lots_of_changes_in_same_batch() {
         let state = MemoryState::default();
@@ -1,5 +1,7 @@
 use fnv::FnvBuildHasher;
 use indexmap::IndexMap;
+use std::hash::Hash;
+use std::mem;
 use std::rc::Rc;
 
 use super::mk_key::MakeKey;
@@ -16,6 +18,8 @@ pub(super) enum KeyedState {
     Quad(FnvHashMap<(DataType, DataType, DataType, DataType), Vec<Row>>),
     Quin(FnvHashMap<(DataType, DataType, DataType, DataType, DataType), Vec<Row>>),
     Sex(FnvHashMap<(DataType, DataType, DataType, DataType, DataType, DataType), Vec<Row>>),
+    /// Keys of more than six columns, which are rare enough to not warrant a tuple of their own.
+    Multi(FnvHashMap<Vec<DataType>, Vec<Row>>),
 }
 
 impl KeyedState {
@@ -27,10 +31,96 @@ impl KeyedState {
             (&KeyedState::Quad(ref m), &KeyType::Quad(ref k)) => m.get(k),
             (&KeyedState::Quin(ref m), &KeyType::Quin(ref k)) => m.get(k),
             (&KeyedState::Sex(ref m), &KeyType::Sex(ref k)) => m.get(k),
+            (&KeyedState::Multi(ref m), &KeyType::Multi(ref k)) => m.get(k),
             _ => unreachable!(),
         }
     }
 
+    /// Iterate over every key in this state along with the rows stored under it.
+    pub(super) fn iter<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, &'a Vec<Row>)> + 'a> {
+        match *self {
+            KeyedState::Single(ref m) => Box::new(m.iter().map(|(k, rs)| (vec![k.clone()], rs))),
+            KeyedState::Double(ref m) => {
+                Box::new(m.iter().map(|(k, rs)| (vec![k.0.clone(), k.1.clone()], rs)))
+            }
+            KeyedState::Tri(ref m) => Box::new(
+                m.iter()
+                    .map(|(k, rs)| (vec![k.0.clone(), k.1.clone(), k.2.clone()], rs)),
+            ),
+            KeyedState::Quad(ref m) => Box::new(
+                m.iter()
+                    .map(|(k, rs)| (vec![k.0.clone(), k.1.clone(), k.2.clone(), k.3.clone()], rs)),
+            ),
+            KeyedState::Quin(ref m) => Box::new(m.iter().map(|(k, rs)| {
+                (
+                    vec![
+                        k.0.clone(),
+                        k.1.clone(),
+                        k.2.clone(),
+                        k.3.clone(),
+                        k.4.clone(),
+                    ],
+                    rs,
+                )
+            })),
+            KeyedState::Sex(ref m) => Box::new(m.iter().map(|(k, rs)| {
+                (
+                    vec![
+                        k.0.clone(),
+                        k.1.clone(),
+                        k.2.clone(),
+                        k.3.clone(),
+                        k.4.clone(),
+                        k.5.clone(),
+                    ],
+                    rs,
+                )
+            })),
+            KeyedState::Multi(ref m) => Box::new(m.iter().map(|(k, rs)| (k.clone(), rs))),
+        }
+    }
+
+    /// Iterate over the rows stored under each key in this state, without materializing the keys.
+    pub(super) fn values<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Vec<Row>> + 'a> {
+        match *self {
+            KeyedState::Single(ref m) => Box::new(m.values()),
+            KeyedState::Double(ref m) => Box::new(m.values()),
+            KeyedState::Tri(ref m) => Box::new(m.values()),
+            KeyedState::Quad(ref m) => Box::new(m.values()),
+            KeyedState::Quin(ref m) => Box::new(m.values()),
+            KeyedState::Sex(ref m) => Box::new(m.values()),
+            KeyedState::Multi(ref m) => Box::new(m.values()),
+        }
+    }
+
+    /// Consume this state, yielding every key along with the rows stored under it.
+    pub(super) fn into_iter(self) -> Box<dyn Iterator<Item = (Vec<DataType>, Vec<Row>)>> {
+        match self {
+            KeyedState::Single(m) => Box::new(m.into_iter().map(|(k, rs)| (vec![k], rs))),
+            KeyedState::Double(m) => Box::new(m.into_iter().map(|(k, rs)| (vec![k.0, k.1], rs))),
+            KeyedState::Tri(m) => Box::new(m.into_iter().map(|(k, rs)| (vec![k.0, k.1, k.2], rs))),
+            KeyedState::Quad(m) => {
+                Box::new(m.into_iter().map(|(k, rs)| (vec![k.0, k.1, k.2, k.3], rs)))
+            }
+            KeyedState::Quin(m) => Box::new(
+                m.into_iter()
+                    .map(|(k, rs)| (vec![k.0, k.1, k.2, k.3, k.4], rs)),
+            ),
+            KeyedState::Sex(m) => Box::new(
+                m.into_iter()
+                    .map(|(k, rs)| (vec![k.0, k.1, k.2, k.3, k.4, k.5], rs)),
+            ),
+            KeyedState::Multi(m) => Box::new(m.into_iter()),
+        }
+    }
+
+    /// Return all keys currently present in this state.
+    pub(super) fn keys(&self) -> Vec<Vec<DataType>> {
+        self.iter().map(|(k, _)| k).collect()
+    }
+
     /// Remove all rows for a randomly chosen key seeded by `seed`, returning that key along with
     /// the number of bytes freed. Returns `None` if map is empty.
     pub(super) fn evict_with_seed(&mut self, seed: usize) -> Option<(u64, Vec<DataType>)> {
@@ -64,6 +154,10 @@ impl KeyedState {
                 m.swap_remove_index(index)
                     .map(|(k, rs)| (rs, vec![k.0, k.1, k.2, k.3, k.4, k.5]))
             }
+            KeyedState::Multi(ref mut m) => {
+                let index = seed % m.len();
+                m.swap_remove_index(index).map(|(k, rs)| (rs, k))
+            }
         }?;
         Some((
             rs.iter()
@@ -74,6 +168,33 @@ impl KeyedState {
         ))
     }
 
+    /// Reserve capacity for at least `additional` more keys in the map.
+    pub(super) fn reserve(&mut self, additional: usize) {
+        match *self {
+            KeyedState::Single(ref mut m) => m.reserve(additional),
+            KeyedState::Double(ref mut m) => m.reserve(additional),
+            KeyedState::Tri(ref mut m) => m.reserve(additional),
+            KeyedState::Quad(ref mut m) => m.reserve(additional),
+            KeyedState::Quin(ref mut m) => m.reserve(additional),
+            KeyedState::Sex(ref mut m) => m.reserve(additional),
+            KeyedState::Multi(ref mut m) => m.reserve(additional),
+        }
+    }
+
+    /// Release unused capacity in the map and its buckets, returning the approximate number of
+    /// bytes released.
+    pub(super) fn shrink_to_fit(&mut self) -> u64 {
+        match *self {
+            KeyedState::Single(ref mut m) => shrink_map(m),
+            KeyedState::Double(ref mut m) => shrink_map(m),
+            KeyedState::Tri(ref mut m) => shrink_map(m),
+            KeyedState::Quad(ref mut m) => shrink_map(m),
+            KeyedState::Quin(ref mut m) => shrink_map(m),
+            KeyedState::Sex(ref mut m) => shrink_map(m),
+            KeyedState::Multi(ref mut m) => shrink_map(m),
+        }
+    }
+
     /// Remove all rows for the given key, returning the number of bytes freed.
     pub(super) fn evict(&mut self, key: &[DataType]) -> u64 {
         match *self {
@@ -93,6 +214,7 @@ impl KeyedState {
             KeyedState::Sex(ref mut m) => {
                 m.swap_remove::<(DataType, _, _, _, _, _)>(&MakeKey::from_key(key))
             }
+            KeyedState::Multi(ref mut m) => m.swap_remove(key),
         }
         .map(|rows| {
             rows.iter()
@@ -104,6 +226,25 @@ impl KeyedState {
     }
 }
 
+/// Release unused capacity held by `m` and by each of its buckets, returning the approximate
+/// number of bytes released.
+fn shrink_map<K: Hash + Eq>(m: &mut FnvHashMap<K, Vec<Row>>) -> u64 {
+    let mut freed = 0;
+    for rs in m.values_mut() {
+        freed += (rs.capacity() - rs.len()) * mem::size_of::<Row>();
+        rs.shrink_to_fit();
+    }
+
+    let capacity = m.capacity();
+    if capacity > m.len() {
+        // rebuilding the map allocates only as much as is needed for the current entries
+        *m = mem::replace(m, FnvHashMap::default()).into_iter().collect();
+        let entry = mem::size_of::<(K, Vec<Row>)>() + mem::size_of::<usize>();
+        freed += capacity.saturating_sub(m.capacity()) * entry;
+    }
+    freed as u64
+}
+
 impl<'a> Into<KeyedState> for &'a [usize] {
     fn into(self) -> KeyedState {
         match self.len() {
@@ -114,7 +255,7 @@ impl<'a> Into<KeyedState> for &'a [usize] {
             4 => KeyedState::Quad(FnvHashMap::default()),
             5 => KeyedState::Quin(FnvHashMap::default()),
             6 => KeyedState::Sex(FnvHashMap::default()),
-            x => panic!("invalid compound key of length: {}", x),
+            _ => KeyedState::Multi(FnvHashMap::default()),
         }
     }
 }
@@ -1,16 +1,36 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::mem;
 use std::rc::Rc;
+use std::slice;
+use std::time::{Duration, Instant};
 
 use rand::{self, Rng};
 
 use crate::prelude::*;
+use crate::state::hll::HyperLogLog;
 use crate::state::single_state::SingleState;
+use crate::state::{bucket_extreme, equi_depth_histogram, sum_values, LookupStats};
 use common::SizeOf;
 
 #[derive(Default)]
 pub struct MemoryState {
     state: Vec<SingleState>,
     by_tag: HashMap<Tag, usize>,
+    /// Lazy indices that have not yet been moved into `state`, along with the index itself if it
+    /// has been built by a lookup.
+    lazy: Vec<(Vec<usize>, RefCell<Option<SingleState>>)>,
+    track_insert_times: bool,
+    min_residency: Option<Duration>,
+    track_access_times: bool,
+    /// Advanced on every tracked access, so that accesses to different indices can be ordered.
+    clock: Cell<u64>,
+    stats: Cell<LookupStats>,
+    /// Distinct value estimates for the tracked columns.
+    distinct: Vec<(usize, HyperLogLog)>,
     mem_size: u64,
 }
 
@@ -28,6 +48,9 @@ impl SizeOf for MemoryState {
 
 impl State for MemoryState {
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
+        // an eagerly maintained index replaces any lazy one
+        self.lazy.retain(|(c, _)| &c[..] != columns);
+
         let (i, exists) = if let Some(i) = self.state_for(columns) {
             // already keyed by this key; just adding tags
             (i, true)
@@ -48,6 +71,12 @@ impl State for MemoryState {
 
         self.state
             .push(SingleState::new(columns, partial.is_some()));
+        if self.track_insert_times {
+            self.state.last_mut().unwrap().track_insert_times();
+        }
+        if self.track_access_times && partial.is_some() {
+            self.state.last_mut().unwrap().track_access_times();
+        }
 
         if !self.state.is_empty() && partial.is_none() {
             // we need to *construct* the index!
@@ -64,6 +93,51 @@ impl State for MemoryState {
         }
     }
 
+    fn add_lazy_key(&mut self, columns: &[usize]) {
+        assert!(!self.state.is_empty() && !self.is_partial());
+        if self.state_for(columns).is_some() || self.lazy.iter().any(|(c, _)| &c[..] == columns) {
+            return;
+        }
+        self.lazy.push((columns.to_vec(), RefCell::new(None)));
+    }
+
+    fn reindex(&mut self, old_columns: &[usize], new_columns: &[usize]) {
+        let i = self
+            .state_for(old_columns)
+            .expect("reindex on non-indexed column set");
+        if old_columns == new_columns {
+            return;
+        }
+        assert!(
+            self.state_for(new_columns).is_none(),
+            "reindex onto an existing index"
+        );
+        self.lazy.retain(|(c, _)| &c[..] != new_columns);
+
+        let partial = self.state[i].partial();
+        let mut new = SingleState::new(new_columns, partial);
+        if self.track_insert_times {
+            new.track_insert_times();
+        }
+        if self.track_access_times && partial {
+            new.track_access_times();
+        }
+
+        let old = mem::replace(&mut self.state[i], new);
+        if partial {
+            let freed: u64 = old
+                .into_rows()
+                .filter(|r| Rc::strong_count(&r.0) == 1)
+                .map(|r| r.deep_size_of())
+                .sum();
+            self.mem_size = self.mem_size.saturating_sub(freed);
+        } else {
+            for r in old.into_rows() {
+                self.state[i].insert_row(r);
+            }
+        }
+    }
+
     fn is_useful(&self) -> bool {
         !self.state.is_empty()
     }
@@ -111,15 +185,67 @@ impl State for MemoryState {
     }
 
     fn rows(&self) -> usize {
-        self.state.iter().map(SingleState::rows).sum()
+        // a full index holds every record unless it has been cleared with `clear_index`, in
+        // which case it holds a subset of what the other full indices hold
+        let full = self
+            .state
+            .iter()
+            .filter(|s| !s.partial())
+            .map(SingleState::rows)
+            .max();
+        if let Some(rows) = full {
+            return rows;
+        }
+        if self.state.len() == 1 {
+            return self.state[0].rows();
+        }
+
+        // partial indices may hold different records, but share the copy of any record they have
+        // in common, so count distinct copies
+        self.state
+            .iter()
+            .flat_map(|s| s.values())
+            .flat_map(|rs| rs.iter().map(|r| &**r as *const Vec<DataType>))
+            .collect::<HashSet<_>>()
+            .len()
     }
 
     fn mark_filled(&mut self, key: Vec<DataType>, tag: Tag) {
         debug_assert!(!self.state.is_empty(), "filling uninitialized index");
         let index = self.by_tag[&tag];
+        if self.track_access_times {
+            self.state[index].record_access(key.clone(), self.tick());
+        }
         self.state[index].mark_filled(key);
     }
 
+    fn mark_filled_batch(&mut self, keys: Vec<Vec<DataType>>, tag: Tag) {
+        debug_assert!(!self.state.is_empty(), "filling uninitialized index");
+        let index = self.by_tag[&tag];
+        for key in keys {
+            if self.track_access_times {
+                self.state[index].record_access(key.clone(), self.tick());
+            }
+            self.state[index].mark_filled(key);
+        }
+    }
+
+    fn insert_many(&mut self, rows: Vec<Vec<DataType>>, partial_tag: Option<Tag>) -> usize {
+        if partial_tag.is_none() {
+            // every row lands in each fully materialized index, so make room for them up front
+            // rather than growing the maps as we go.
+            self.promote_lazy_keys();
+            for s in self.state.iter_mut().filter(|s| !s.partial()) {
+                s.reserve(rows.len());
+            }
+        }
+
+        rows.into_iter()
+            .map(|r| self.insert(r, partial_tag))
+            .filter(|&hit| hit)
+            .count()
+    }
+
     fn mark_hole(&mut self, key: &[DataType], tag: Tag) {
         debug_assert!(!self.state.is_empty(), "filling uninitialized index");
         let index = self.by_tag[&tag];
@@ -129,16 +255,131 @@ impl State for MemoryState {
 
     fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a> {
         debug_assert!(!self.state.is_empty(), "lookup on uninitialized index");
+        match self.state_for(columns) {
+            Some(index) => self.lookup_at(index, key),
+            None => {
+                let rs = self
+                    .lookup_lazy(columns, key)
+                    .expect("lookup on non-indexed column set");
+                self.count_lookup(&rs);
+                rs
+            }
+        }
+    }
+
+    fn lookup_many<'a>(&'a self, columns: &[usize], keys: &[KeyType]) -> Vec<LookupResult<'a>> {
+        debug_assert!(!self.state.is_empty(), "lookup on uninitialized index");
+        match self.state_for(columns) {
+            Some(index) => keys.iter().map(|key| self.lookup_at(index, key)).collect(),
+            None => keys.iter().map(|key| self.lookup(columns, key)).collect(),
+        }
+    }
+
+    fn contains_key(&self, columns: &[usize], key: &KeyType) -> bool {
+        let index = match self.state_for(columns) {
+            Some(index) => index,
+            None => return self.lookup_len(columns, key).map_or(false, |n| n > 0),
+        };
+        match self.lookup_at(index, key) {
+            LookupResult::Some(rs) => self.state[index].partial() || rs.len() > 0,
+            LookupResult::Missing => false,
+        }
+    }
+
+    fn peek<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a> {
+        // go straight to the index, so that the peek is neither counted in the lookup stats nor
+        // recorded as an access
+        if let Some(index) = self.state_for(columns) {
+            return self.state[index].lookup(key);
+        }
+
+        let unbuilt = self
+            .lazy
+            .iter()
+            .any(|(c, index)| &c[..] == columns && index.borrow().is_none());
+        if !unbuilt {
+            return self
+                .lookup_lazy(columns, key)
+                .expect("lookup on non-indexed column set");
+        }
+
+        // scan the full index rather than building the lazy one
+        let key = key_values(key);
+        let rows = self.state[0]
+            .values()
+            .flat_map(|rs| rs.iter())
+            .filter(|r| columns.iter().zip(&key).all(|(&c, k)| &r[c] == k))
+            .map(|r| Vec::clone(&*r.0))
+            .collect();
+        LookupResult::Some(RecordResult::Owned(rows))
+    }
+
+    fn iter_index<'a>(
+        &'a self,
+        columns: &[usize],
+    ) -> Option<Box<dyn Iterator<Item = &'a [Row]> + 'a>> {
+        // lazy indices that have not been built yet live behind a RefCell, and can't be borrowed
+        // from here
+        Some(self.state[self.state_for(columns)?].buckets())
+    }
+
+    fn track_bucket_extremes(&mut self, columns: &[usize], col: usize) {
+        let index = self
+            .state_for(columns)
+            .expect("track_bucket_extremes on non-indexed column set");
+        self.state[index].track_extremes(col);
+    }
+
+    fn bucket_min(&self, columns: &[usize], col: usize, key: &[DataType]) -> Option<DataType> {
+        match self.tracked_extremes(columns, col, key) {
+            Some(extremes) => extremes.map(|(min, _)| min.clone()),
+            None => bucket_extreme(self.peek(columns, &KeyType::from(key)), col, false),
+        }
+    }
+
+    fn bucket_max(&self, columns: &[usize], col: usize, key: &[DataType]) -> Option<DataType> {
+        match self.tracked_extremes(columns, col, key) {
+            Some(extremes) => extremes.map(|(_, max)| max.clone()),
+            None => bucket_extreme(self.peek(columns, &KeyType::from(key)), col, true),
+        }
+    }
+
+    fn iter_buckets<'a>(
+        &'a self,
+        columns: &[usize],
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, RecordResult<'a>)> + 'a> {
         let index = self
             .state_for(columns)
-            .expect("lookup on non-indexed column set");
-        self.state[index].lookup(key)
+            .expect("iter_buckets on non-indexed column set");
+        Box::new(
+            self.state[index]
+                .iter()
+                .map(|(k, rs)| (k, RecordResult::Borrowed(&rs[..]))),
+        )
     }
 
     fn keys(&self) -> Vec<Vec<usize>> {
         self.state.iter().map(|s| s.key().to_vec()).collect()
     }
 
+    fn locate(&self, row: &[DataType]) -> Vec<(Vec<usize>, Vec<DataType>, bool)> {
+        self.state
+            .iter()
+            .map(|s| {
+                let key: Vec<DataType> = s.key().iter().map(|&c| row[c].clone()).collect();
+                let present = s.contains_key(&KeyType::from(&key[..]));
+                (s.key().to_vec(), key, present)
+            })
+            .collect()
+    }
+
+    fn all_keys_across_indices(&self) -> HashMap<Vec<usize>, Vec<Vec<DataType>>> {
+        self.state
+            .iter()
+            .map(|s| (s.key().to_vec(), s.keys()))
+            .collect()
+    }
+
     fn cloned_records(&self) -> Vec<Vec<DataType>> {
         #[allow(clippy::ptr_arg)]
         fn fix<'a>(rs: &'a Vec<Row>) -> impl Iterator<Item = Vec<DataType>> + 'a {
@@ -149,10 +390,170 @@ impl State for MemoryState {
         self.state[0].values().flat_map(fix).collect()
     }
 
+    fn column_histogram(&self, col: usize, buckets: usize) -> Vec<(DataType, DataType, usize)> {
+        if self.state.is_empty() {
+            return Vec::new();
+        }
+
+        assert!(!self.state[0].partial());
+        let values = self.state[0]
+            .values()
+            .flat_map(|rs| rs.iter().map(|r| r[col].clone()))
+            .collect();
+        equi_depth_histogram(values, buckets)
+    }
+
+    fn sum_column(&self, col: usize) -> Option<DataType> {
+        if self.state.is_empty() {
+            return Some(0i64.into());
+        }
+
+        assert!(!self.state[0].partial());
+        sum_values(
+            self.state[0]
+                .values()
+                .flat_map(|rs| rs.iter().map(|r| &r[col])),
+        )
+    }
+
+    fn track_distinct(&mut self, col: usize) {
+        if self.distinct.iter().any(|&(c, _)| c == col) {
+            return;
+        }
+
+        let mut hll = HyperLogLog::default();
+        if let Some(s) = self.state.first() {
+            for rs in s.values() {
+                for r in rs {
+                    hll.insert(&r[col]);
+                }
+            }
+        }
+        self.distinct.push((col, hll));
+    }
+
+    fn approx_count_distinct(&self, col: usize) -> Option<u64> {
+        self.distinct
+            .iter()
+            .find(|&&(c, _)| c == col)
+            .map(|(_, hll)| hll.estimate())
+    }
+
+    fn track_insert_times(&mut self) {
+        self.track_insert_times = true;
+        for s in &mut self.state {
+            s.track_insert_times();
+        }
+    }
+
+    fn row_age(&self, columns: &[usize], key: &[DataType]) -> Option<Duration> {
+        let index = &self.state[self.state_for(columns)?];
+        if !index.contains_key(&KeyType::from(key)) {
+            return None;
+        }
+        index.inserted_at(key).map(|t| t.elapsed())
+    }
+
+    fn evict_older_than(&mut self, age: Duration) -> (Vec<(Vec<usize>, Vec<Vec<DataType>>)>, u64) {
+        assert!(self.track_insert_times, "insert times are not tracked");
+        let age = cmp::max(age, self.min_residency.unwrap_or_default());
+        let cutoff = match Instant::now().checked_sub(age) {
+            Some(cutoff) => cutoff,
+            None => return (Vec::new(), 0),
+        };
+
+        let mut evicted = Vec::new();
+        let mut bytes = 0;
+        for s in self.state.iter_mut().filter(|s| s.partial()) {
+            // keys may have been evicted since they were last inserted into
+            let keys: Vec<_> = s
+                .take_inserted_before(cutoff)
+                .into_iter()
+                .filter(|k| s.contains_key(&KeyType::from(&k[..])))
+                .collect();
+            if !keys.is_empty() {
+                bytes += s.evict_keys(&keys);
+                evicted.push((s.key().to_vec(), keys));
+            }
+        }
+        self.mem_size = self.mem_size.saturating_sub(bytes);
+        (evicted, bytes)
+    }
+
+    fn track_access_times(&mut self) {
+        self.track_access_times = true;
+        for s in self.state.iter_mut().filter(|s| s.partial()) {
+            s.track_access_times();
+        }
+    }
+
+    fn evict_lru(&mut self, bytes_to_free: u64) -> (Vec<(Vec<usize>, Vec<Vec<DataType>>)>, u64) {
+        assert!(self.track_access_times, "access times are not tracked");
+        let protect_since = self.protect_since();
+        let mut candidates = Vec::new();
+        for (i, s) in self.state.iter().enumerate().filter(|(_, s)| s.partial()) {
+            for (tick, key) in s.access_times().unwrap_or_default() {
+                let protected = protect_since
+                    .and_then(|cutoff| s.inserted_at(&key).map(|t| t >= cutoff))
+                    .unwrap_or(false);
+                if !protected {
+                    candidates.push((tick, i, key));
+                }
+            }
+        }
+        candidates.sort_by_key(|&(tick, _, _)| tick);
+
+        let mut evicted: Vec<(Vec<usize>, Vec<Vec<DataType>>)> = Vec::new();
+        let mut bytes = 0;
+        for (_, i, key) in candidates {
+            if bytes >= bytes_to_free {
+                break;
+            }
+            bytes += self.state[i].evict_keys(slice::from_ref(&key));
+            let columns = self.state[i].key();
+            match evicted.iter_mut().find(|(c, _)| &c[..] == columns) {
+                Some((_, keys)) => keys.push(key),
+                None => evicted.push((columns.to_vec(), vec![key])),
+            }
+        }
+        self.mem_size = self.mem_size.saturating_sub(bytes);
+        (evicted, bytes)
+    }
+
+    fn evict_range(
+        &mut self,
+        columns: &[usize],
+        upper_bound: &[DataType],
+    ) -> (Vec<Vec<DataType>>, u64) {
+        let index = self
+            .state_for(columns)
+            .expect("evict_range on non-indexed column set");
+        assert!(
+            self.state[index].partial(),
+            "evict_range on fully materialized index"
+        );
+
+        let keys: Vec<_> = self.state[index]
+            .keys()
+            .into_iter()
+            .filter(|k| &k[..] < upper_bound)
+            .collect();
+        let bytes = self.state[index].evict_keys(&keys);
+        self.mem_size = self.mem_size.saturating_sub(bytes);
+        (keys, bytes)
+    }
+
+    fn set_min_residency(&mut self, min_residency: Duration) {
+        self.track_insert_times();
+        self.min_residency = Some(min_residency);
+    }
+
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
         let mut rng = rand::thread_rng();
         let index = rng.gen_range(0, self.state.len());
-        let (bytes_freed, keys) = self.state[index].evict_random_keys(count, &mut rng);
+        let protect_since = self.protect_since();
+        let (bytes_freed, keys) =
+            self.state[index].evict_random_keys(count, &mut rng, protect_since);
         self.mem_size = self.mem_size.saturating_sub(bytes_freed);
         (self.state[index].key(), keys, bytes_freed)
     }
@@ -168,22 +569,302 @@ impl State for MemoryState {
         })
     }
 
+    fn evictable_keys(&self, tag: Tag) -> Vec<Vec<DataType>> {
+        let index = match self.by_tag.get(&tag) {
+            Some(&index) => index,
+            None => return Vec::new(),
+        };
+
+        if self.by_tag.values().filter(|&&i| i == index).count() > 1 {
+            // evicting from a shared index would also evict for the other tags
+            return Vec::new();
+        }
+
+        let s = &self.state[index];
+        match self.protect_since() {
+            Some(cutoff) => s
+                .keys()
+                .into_iter()
+                .filter(|k| s.inserted_at(k).map(|t| t < cutoff).unwrap_or(true))
+                .collect(),
+            None => s.keys(),
+        }
+    }
+
+    fn retain_keys(
+        &mut self,
+        columns: &[usize],
+        keep: &HashSet<Vec<DataType>>,
+    ) -> Vec<Vec<DataType>> {
+        let index = self
+            .state_for(columns)
+            .expect("retain_keys on non-indexed column set");
+        let dropped: Vec<_> = self.state[index]
+            .keys()
+            .into_iter()
+            .filter(|k| !keep.contains(k))
+            .collect();
+
+        if !self.state[index].partial() {
+            // the rows must also leave every other index
+            for key in &dropped {
+                let rows: Vec<_> = match self.state[index].lookup(&KeyType::from(key)) {
+                    LookupResult::Some(rs) => rs.into_iter().map(Cow::into_owned).collect(),
+                    LookupResult::Missing => unreachable!(),
+                };
+                for row in rows {
+                    self.remove(&row);
+                }
+            }
+        }
+
+        let bytes = self.state[index].evict_keys(&dropped);
+        self.mem_size = self.mem_size.saturating_sub(bytes);
+        dropped
+    }
+
+    fn remove_all_matching(&mut self, r: &[DataType]) -> usize {
+        // count the copies up front: remove_row assumes that a negative record is present
+        let copies = self
+            .state
+            .iter()
+            .map(|s| {
+                let key = KeyType::from(s.key().iter().map(|&c| &r[c]));
+                match s.lookup(&key) {
+                    LookupResult::Some(rs) => rs.into_iter().filter(|rr| &rr[..] == r).count(),
+                    LookupResult::Missing => 0,
+                }
+            })
+            .max()
+            .unwrap_or(0);
+
+        for _ in 0..copies {
+            self.remove(r);
+        }
+        copies
+    }
+
+    fn dedup(&mut self) -> usize {
+        if self.state.is_empty() {
+            return 0;
+        }
+
+        assert!(!self.state[0].partial());
+        let mut duplicates = Vec::new();
+        for rs in self.state[0].values() {
+            let mut seen = HashSet::with_capacity(rs.len());
+            for r in rs {
+                if !seen.insert(&r[..]) {
+                    duplicates.push(Vec::clone(&**r));
+                }
+            }
+        }
+
+        // removing a record takes out one copy from every index
+        for r in &duplicates {
+            self.remove(r);
+        }
+        duplicates.len()
+    }
+
+    fn footprint(&self) -> u64 {
+        let mut seen = HashSet::new();
+        let eager: u64 = self.state.iter().map(|s| s.footprint(&mut seen)).sum();
+        let lazy: u64 = self
+            .lazy
+            .iter()
+            .filter_map(|(_, index)| Some(index.borrow().as_ref()?.footprint(&mut seen)))
+            .sum();
+        eager + lazy
+    }
+
+    fn shrink_to_fit(&mut self) -> u64 {
+        self.state.iter_mut().map(SingleState::shrink_to_fit).sum()
+    }
+
+    fn stats(&self) -> LookupStats {
+        self.stats.get()
+    }
+
     fn clear(&mut self) {
         for state in &mut self.state {
             state.clear();
         }
+        for (_, index) in &mut self.lazy {
+            *index.get_mut() = None;
+        }
+        for (_, hll) in &mut self.distinct {
+            hll.clear();
+        }
+        self.stats.set(LookupStats::default());
         self.mem_size = 0;
     }
+
+    fn clear_index(&mut self, columns: &[usize]) -> bool {
+        let index = match self.state_for(columns) {
+            Some(index) => index,
+            None => {
+                // a lazy index shares its records with the other indices, and is simply rebuilt
+                // on its next lookup
+                return match self.lazy.iter_mut().find(|(c, _)| &c[..] == columns) {
+                    Some((_, index)) => {
+                        *index.get_mut() = None;
+                        true
+                    }
+                    None => false,
+                };
+            }
+        };
+        let freed: u64 = self.state[index]
+            .buckets()
+            .flat_map(|rs| rs.iter())
+            .filter(|r| Rc::strong_count(&r.0) == 1)
+            .map(SizeOf::deep_size_of)
+            .sum();
+        self.state[index].clear();
+        self.mem_size = self.mem_size.saturating_sub(freed);
+        true
+    }
+
+    fn into_parts(self: Box<Self>) -> (Vec<Vec<usize>>, Vec<Vec<DataType>>) {
+        let keys = self.keys();
+        let mut state = self.state;
+        if state.is_empty() {
+            return (keys, Vec::new());
+        }
+
+        assert!(!state[0].partial());
+        // drop the other indices first so that we hold the only reference to each row
+        state.truncate(1);
+        let rows = state
+            .pop()
+            .unwrap()
+            .into_rows()
+            .map(|r| Rc::try_unwrap(r.0).unwrap_or_else(|r| Vec::clone(&*r)))
+            .collect();
+        (keys, rows)
+    }
+
+    fn into_keyed_iter(
+        self: Box<Self>,
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, Vec<Vec<DataType>>)>> {
+        let MemoryState { mut state, .. } = *self;
+        if state.is_empty() {
+            return Box::new(iter::empty());
+        }
+
+        // drop the other indices first so that we hold the only reference to each row
+        state.truncate(1);
+        Box::new(state.pop().unwrap().into_buckets().map(|(key, rows)| {
+            let rows = rows
+                .into_iter()
+                .map(|r| Rc::try_unwrap(r.0).unwrap_or_else(|r| Vec::clone(&*r)))
+                .collect();
+            (key, rows)
+        }))
+    }
 }
 
 impl MemoryState {
+    /// Returns the tracked extremes of column `col` in the bucket for `key`, or `None` if they are
+    /// not tracked for the index keyed on `cols`.
+    fn tracked_extremes(
+        &self,
+        cols: &[usize],
+        col: usize,
+        key: &[DataType],
+    ) -> Option<Option<(&DataType, &DataType)>> {
+        self.state[self.state_for(cols)?].extremes(col, key)
+    }
+
+    /// Look up `key` in the index at `index`, recording the access if the key is present and
+    /// access times are tracked.
+    fn lookup_at<'a>(&'a self, index: usize, key: &KeyType) -> LookupResult<'a> {
+        let rs = self.state[index].lookup(key);
+        self.count_lookup(&rs);
+        if self.track_access_times {
+            if let LookupResult::Some(_) = rs {
+                self.state[index].record_access(key_values(key), self.tick());
+            }
+        }
+        rs
+    }
+
+    fn count_lookup(&self, rs: &LookupResult) {
+        let mut stats = self.stats.get();
+        stats.count(rs);
+        self.stats.set(stats);
+    }
+
+    /// Advance the access clock, and return the new tick.
+    fn tick(&self) -> u64 {
+        let tick = self.clock.get() + 1;
+        self.clock.set(tick);
+        tick
+    }
+
+    /// Returns the time after which filled keys are protected from eviction, if any.
+    fn protect_since(&self) -> Option<Instant> {
+        Instant::now().checked_sub(self.min_residency?)
+    }
+
     /// Returns the index in `self.state` of the index keyed on `cols`, or None if no such index
     /// exists.
     fn state_for(&self, cols: &[usize]) -> Option<usize> {
         self.state.iter().position(|s| s.key() == cols)
     }
 
+    /// Look up `key` in the lazy index keyed on `cols`, building the index if this is the first
+    /// lookup against it. Returns None if no such index exists.
+    ///
+    /// Since the index lives behind a `RefCell` until it is moved into `self.state`, the matching
+    /// records are returned as copies.
+    fn lookup_lazy(&self, cols: &[usize], key: &KeyType) -> Option<LookupResult<'static>> {
+        let (_, index) = self.lazy.iter().find(|(c, _)| &c[..] == cols)?;
+        let mut index = index.borrow_mut();
+        let index = index.get_or_insert_with(|| {
+            let mut index = SingleState::new(cols, false);
+            for rs in self.state[0].values() {
+                for r in rs {
+                    index.insert_row(r.clone());
+                }
+            }
+            index
+        });
+
+        let res = match index.lookup(key) {
+            LookupResult::Some(rs) => LookupResult::Some(RecordResult::Owned(
+                rs.into_iter().map(Cow::into_owned).collect(),
+            )),
+            LookupResult::Missing => LookupResult::Missing,
+        };
+        Some(res)
+    }
+
+    /// Move lazy indices that have been built by a lookup into `self.state`, so that they are
+    /// maintained by subsequent writes.
+    fn promote_lazy_keys(&mut self) {
+        if self.lazy.is_empty() {
+            return;
+        }
+
+        let (built, lazy) = self
+            .lazy
+            .drain(..)
+            .partition(|(_, index)| index.borrow().is_some());
+        self.lazy = lazy;
+        self.state.extend(
+            built
+                .into_iter()
+                .filter_map(|(_, index)| index.into_inner()),
+        );
+    }
+
     fn insert(&mut self, r: Vec<DataType>, partial_tag: Option<Tag>) -> bool {
+        self.promote_lazy_keys();
+        for (col, hll) in &mut self.distinct {
+            hll.insert(&r[*col]);
+        }
         let r = Rc::new(r);
 
         if let Some(tag) = partial_tag {
@@ -196,8 +877,12 @@ impl MemoryState {
                     return true;
                 }
             };
-            self.mem_size += r.deep_size_of();
-            self.state[i].insert_row(Row::from(r))
+            let size = r.deep_size_of();
+            let hit = self.state[i].insert_row(Row::from(r));
+            if hit {
+                self.mem_size += size;
+            }
+            hit
         } else {
             let mut hit_any = false;
             for i in 0..self.state.len() {
@@ -211,6 +896,7 @@ impl MemoryState {
     }
 
     fn remove(&mut self, r: &[DataType]) -> bool {
+        self.promote_lazy_keys();
         let mut hit = false;
         for s in &mut self.state {
             if let Some(row) = s.remove_row(r, &mut hit) {
@@ -224,6 +910,30 @@ impl MemoryState {
     }
 }
 
+/// Returns the values that make up `key`.
+fn key_values(key: &KeyType) -> Vec<DataType> {
+    match *key {
+        KeyType::Single(k) => vec![k.clone()],
+        KeyType::Double((ref k0, ref k1)) => vec![k0.clone(), k1.clone()],
+        KeyType::Tri((ref k0, ref k1, ref k2)) => vec![k0.clone(), k1.clone(), k2.clone()],
+        KeyType::Quad((ref k0, ref k1, ref k2, ref k3)) => {
+            vec![k0.clone(), k1.clone(), k2.clone(), k3.clone()]
+        }
+        KeyType::Quin((ref k0, ref k1, ref k2, ref k3, ref k4)) => {
+            vec![k0.clone(), k1.clone(), k2.clone(), k3.clone(), k4.clone()]
+        }
+        KeyType::Sex((ref k0, ref k1, ref k2, ref k3, ref k4, ref k5)) => vec![
+            k0.clone(),
+            k1.clone(),
+            k2.clone(),
+            k3.clone(),
+            k4.clone(),
+            k5.clone(),
+        ],
+        KeyType::Multi(ref k) => k.clone(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -278,4 +988,971 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn memory_state_all_keys_across_indices() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1, 2], Some(vec![Tag(0)]));
+        insert(&mut state, vec![10.into(), "Cat".into(), 1.into()]);
+        state.mark_filled(vec!["Dog".into(), 2.into()], Tag(0));
+
+        let keys = state.all_keys_across_indices();
+        assert_eq!(keys[&vec![0]], vec![vec![10.into()]]);
+        assert_eq!(keys[&vec![1, 2]], vec![vec!["Dog".into(), 2.into()]]);
+    }
+
+    #[test]
+    fn memory_state_lookup_in() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        state.mark_filled(vec![2.into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into()]);
+
+        let keys = vec![vec![1.into()], vec![2.into()], vec![3.into()]];
+        let (hits, misses) = state.lookup_in(&[0], &keys);
+        assert_eq!(
+            hits.iter().map(RecordResult::len).collect::<Vec<_>>(),
+            vec![1, 0]
+        );
+        assert_eq!(misses, vec![vec![3.into()]]);
+    }
+
+    #[test]
+    fn memory_state_remove_all_matching() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        let row: Vec<DataType> = vec![1.into(), "A".into()];
+        insert(&mut state, row.clone());
+        insert(&mut state, row.clone());
+        insert(&mut state, vec![1.into(), "B".into()]);
+
+        assert_eq!(state.remove_all_matching(&row), 2);
+        assert_eq!(state.remove_all_matching(&row), 0);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&1.into())), Some(1));
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"A".into())),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn memory_state_lookup_prefix() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0, 1, 2], None);
+        insert(&mut state, vec![1.into(), "A".into(), 1.into()]);
+        insert(&mut state, vec![1.into(), "A".into(), 2.into()]);
+        insert(&mut state, vec![1.into(), "B".into(), 1.into()]);
+        insert(&mut state, vec![2.into(), "A".into(), 1.into()]);
+
+        assert_eq!(
+            state
+                .lookup_prefix(&[0, 1, 2], &[1.into(), "A".into()])
+                .len(),
+            2
+        );
+        assert_eq!(state.lookup_prefix(&[0, 1, 2], &[1.into()]).len(), 3);
+        assert_eq!(state.lookup_prefix(&[0, 1, 2], &[]).len(), 4);
+        assert!(state.lookup_prefix(&[0, 1, 2], &[3.into()]).is_empty());
+    }
+
+    #[test]
+    fn memory_state_get_or_insert_with() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+
+        let mut computed = 0;
+        let mut compute = || {
+            computed += 1;
+            vec![vec![1.into(), "A".into()], vec![1.into(), "B".into()]]
+        };
+        assert_eq!(
+            state
+                .get_or_insert_with(&[0], Tag(0), &[1.into()], &mut compute)
+                .len(),
+            2
+        );
+        assert_eq!(
+            state
+                .get_or_insert_with(&[0], Tag(0), &[1.into()], &mut compute)
+                .len(),
+            2
+        );
+        assert_eq!(computed, 1);
+    }
+
+    #[test]
+    fn memory_state_reindex() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], Some(vec![Tag(0)]));
+        state.mark_filled(vec!["Cat".into()], Tag(0));
+        insert(&mut state, vec![10.into(), "Cat".into(), 1.into()]);
+        insert(&mut state, vec![20.into(), "Dog".into(), 1.into()]);
+
+        state.reindex(&[0], &[2]);
+        assert_eq!(state.keys(), vec![vec![2], vec![1]]);
+        assert_eq!(state.lookup_len(&[2], &KeyType::Single(&1.into())), Some(2));
+
+        // the partial index keeps its tag, but starts out empty
+        state.reindex(&[1], &[0, 1]);
+        let key = vec![10.into(), "Cat".into()];
+        assert_eq!(state.lookup_len(&[0, 1], &KeyType::from(&key[..])), None);
+        state.mark_filled(key.clone(), Tag(0));
+        assert_eq!(state.lookup_len(&[0, 1], &KeyType::from(&key[..])), Some(0));
+    }
+
+    #[test]
+    fn memory_state_lookup_all_indices() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], Some(vec![Tag(0)]));
+        let row: Vec<DataType> = vec![10.into(), "Cat".into()];
+        insert(&mut state, row.clone());
+
+        let results = state.lookup_all_indices(&[
+            (vec![0], KeyType::Single(&row[0])),
+            (vec![1], KeyType::Single(&row[1])),
+        ]);
+        assert_eq!(results.len(), 2);
+        match results[0] {
+            LookupResult::Some(ref rs) => assert_eq!(rs.len(), 1),
+            LookupResult::Missing => panic!("full index should never miss"),
+        }
+        // the partial index has not been filled for "Cat"
+        assert!(match results[1] {
+            LookupResult::Missing => true,
+            LookupResult::Some(_) => false,
+        });
+    }
+
+    #[test]
+    fn memory_state_locate() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1, 2], Some(vec![Tag(0)]));
+        let row: Vec<DataType> = vec![10.into(), "Cat".into(), 1.into()];
+        insert(&mut state, row.clone());
+
+        assert_eq!(
+            state.locate(&row),
+            vec![
+                (vec![0], vec![10.into()], true),
+                (vec![1, 2], vec!["Cat".into(), 1.into()], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_state_column_histogram() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        for i in 0..10 {
+            insert(&mut state, vec![i.into(), (i % 3).into()]);
+        }
+
+        assert_eq!(
+            state.column_histogram(0, 3),
+            vec![
+                (0.into(), 3.into(), 4),
+                (4.into(), 7.into(), 4),
+                (8.into(), 9.into(), 2),
+            ]
+        );
+        assert_eq!(state.column_histogram(1, 1), vec![(0.into(), 2.into(), 10)]);
+    }
+
+    #[test]
+    fn memory_state_sum_column() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        insert(&mut state, vec![1.into(), 2.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), DataType::None, "B".into()]);
+        insert(&mut state, vec![3.into(), 5.into(), "C".into()]);
+
+        assert_eq!(state.sum_column(1), Some(7.into()));
+        assert_eq!(state.sum_column(2), None);
+
+        insert(&mut state, vec![4.into(), 0.5.into(), "D".into()]);
+        assert_eq!(state.sum_column(1), Some(7.5.into()));
+    }
+
+    #[test]
+    fn memory_state_evictable_keys() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.add_key(&[1], Some(vec![Tag(1), Tag(2)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        state.mark_filled(vec!["A".into()], Tag(1));
+
+        assert_eq!(state.evictable_keys(Tag(0)), vec![vec![1.into()]]);
+        assert!(state.evictable_keys(Tag(1)).is_empty());
+        assert!(state.evictable_keys(Tag(2)).is_empty());
+        assert!(state.evictable_keys(Tag(3)).is_empty());
+    }
+
+    #[test]
+    fn memory_state_one_per_key() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        for i in 0..6 {
+            insert(&mut state, vec![i.into(), (i % 2).into()]);
+        }
+        state.process_records(&mut vec![(vec![4.into(), 0.into()], false)].into(), None);
+
+        let mut rows = state.one_per_key(&[1]);
+        rows.sort_by(|a, b| a[1].cmp(&b[1]));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][1], 0.into());
+        assert_eq!(rows[1][1], 1.into());
+
+        // the bucket for key 4 is left empty by the delete
+        assert_eq!(state.one_per_key(&[0]).len(), 5);
+    }
+
+    #[test]
+    fn memory_state_lazy_key() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        state.add_lazy_key(&[1]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+
+        // the first lookup builds the index from the existing records
+        match state.lookup(&[1], &KeyType::Single(&"A".into())) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows.len(), 2),
+            _ => unreachable!(),
+        };
+
+        // after which it is maintained by writes
+        insert(&mut state, vec![3.into(), "A".into()]);
+        match state.lookup(&[1], &KeyType::Single(&"A".into())) {
+            LookupResult::Some(RecordResult::Borrowed(rows)) => assert_eq!(rows.len(), 3),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn memory_state_approx_count_distinct() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        for i in 0..500 {
+            insert(&mut state, vec![i.into(), (i % 10).into()]);
+        }
+        state.track_distinct(1);
+        state.track_distinct(0);
+        for i in 500..1000 {
+            insert(&mut state, vec![i.into(), (i % 10).into()]);
+        }
+
+        let estimate = state.approx_count_distinct(1).unwrap();
+        assert!((9..=11).contains(&estimate), "estimate was {}", estimate);
+        let estimate = state.approx_count_distinct(0).unwrap();
+        assert!((951..1050).contains(&estimate), "estimate was {}", estimate);
+        assert_eq!(state.approx_count_distinct(2), None);
+    }
+
+    #[test]
+    fn memory_state_bucket_extremes() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        insert(&mut state, vec![1.into(), 5.into()]);
+        state.track_bucket_extremes(&[0], 1);
+        insert(&mut state, vec![1.into(), 3.into()]);
+        insert(&mut state, vec![1.into(), 3.into()]);
+        insert(&mut state, vec![1.into(), 9.into()]);
+
+        let key = [1.into()];
+        assert_eq!(state.bucket_min(&[0], 1, &key), Some(3.into()));
+        assert_eq!(state.bucket_max(&[0], 1, &key), Some(9.into()));
+
+        // removing one copy of the minimum leaves the other in place
+        let mut records: Records = vec![(vec![1.into(), 3.into()], false)].into();
+        state.process_records(&mut records, None);
+        assert_eq!(state.bucket_min(&[0], 1, &key), Some(3.into()));
+
+        let mut records: Records = vec![
+            (vec![1.into(), 3.into()], false),
+            (vec![1.into(), 9.into()], false),
+        ]
+        .into();
+        state.process_records(&mut records, None);
+        assert_eq!(state.bucket_min(&[0], 1, &key), Some(5.into()));
+        assert_eq!(state.bucket_max(&[0], 1, &key), Some(5.into()));
+        assert_eq!(state.bucket_max(&[0], 1, &[2.into()]), None);
+
+        // columns that are not tracked are found by scanning the bucket
+        assert_eq!(state.bucket_max(&[0], 0, &key), Some(1.into()));
+        assert_eq!(state.stats(), LookupStats::default());
+
+        // tracking the same column again is fine
+        state.track_bucket_extremes(&[0], 1);
+
+        // a reindexed state scans its buckets again
+        state.reindex(&[0], &[1]);
+        assert_eq!(state.bucket_max(&[1], 0, &[5.into()]), Some(1.into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "already tracked")]
+    fn memory_state_bucket_extremes_second_column() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.track_bucket_extremes(&[0], 1);
+        state.track_bucket_extremes(&[0], 2);
+    }
+
+    #[test]
+    fn memory_state_wide_key() {
+        let cols: Vec<usize> = (0..7).collect();
+        let row = |last: i32| -> Vec<DataType> {
+            (0..6)
+                .map(DataType::from)
+                .chain(Some(last.into()))
+                .collect()
+        };
+
+        let mut state = MemoryState::default();
+        state.add_key(&cols, Some(vec![Tag(0)]));
+        state.mark_filled(row(1), Tag(0));
+        insert(&mut state, row(1));
+        insert(&mut state, row(1));
+        insert(&mut state, row(2));
+
+        let key = row(1);
+        assert_eq!(state.lookup_len(&cols, &KeyType::from(&key)), Some(2));
+        let key = row(2);
+        assert_eq!(state.lookup_len(&cols, &KeyType::from(&key)), None);
+
+        let mut records: Records = vec![(row(1), false)].into();
+        state.process_records(&mut records, None);
+        let key = row(1);
+        assert_eq!(state.lookup_len(&cols, &KeyType::from(&key)), Some(1));
+
+        state.mark_hole(&row(1), Tag(0));
+        assert_eq!(state.lookup_len(&cols, &KeyType::from(&key)), None);
+    }
+
+    #[test]
+    fn memory_state_evict_lru() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.track_access_times();
+        for i in 1..4 {
+            state.mark_filled(vec![i.into()], Tag(0));
+            insert(&mut state, vec![i.into(), "A".into()]);
+        }
+        state.lookup(&[0], &KeyType::Single(&1.into()));
+        state.lookup(&[0], &KeyType::Single(&3.into()));
+
+        // key 2 was filled after key 1, but key 1 has been looked up since
+        let (evicted, bytes) = state.evict_lru(1);
+        assert_eq!(evicted, vec![(vec![0], vec![vec![2.into()]])]);
+        assert!(bytes > 0);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&2.into())), None);
+
+        let (evicted, _) = state.evict_lru(u64::max_value());
+        assert_eq!(
+            evicted,
+            vec![(vec![0], vec![vec![1.into()], vec![3.into()]])]
+        );
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&3.into())), None);
+    }
+
+    #[test]
+    fn memory_state_footprint() {
+        let mut state = MemoryState::default();
+        state.add_key(&[1], None);
+        let long = "a string that is too long to be stored inline";
+        insert(&mut state, vec![1.into(), long.into()]);
+        let one = state.footprint();
+        assert!(one > state.deep_size_of());
+
+        // a second index adds its overhead, but not another copy of the shared record
+        state.add_key(&[0], None);
+        let two = state.footprint();
+        assert!(two > one);
+        assert!(two - one < state.deep_size_of());
+    }
+
+    #[test]
+    fn memory_state_iter_rows() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0, 1], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![1.into(), "B".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+
+        let mut rows: Vec<_> = state.iter_rows().map(Cow::into_owned).collect();
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                vec![1.into(), "A".into()],
+                vec![1.into(), "B".into()],
+                vec![2.into(), "A".into()],
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_state_lookup_many() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        state.mark_filled(vec![2.into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![1.into(), "B".into()]);
+
+        let (one, two, three) = (1.into(), 2.into(), 3.into());
+        let keys = [
+            KeyType::Single(&one),
+            KeyType::Single(&two),
+            KeyType::Single(&three),
+        ];
+        let lens: Vec<_> = state
+            .lookup_many(&[0], &keys)
+            .into_iter()
+            .map(|r| match r {
+                LookupResult::Some(rs) => Some(rs.len()),
+                LookupResult::Missing => None,
+            })
+            .collect();
+        assert_eq!(lens, vec![Some(2), Some(0), None]);
+    }
+
+    #[test]
+    fn memory_state_clear_index() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], Some(vec![Tag(0)]));
+        state.mark_filled(vec!["A".into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+
+        assert!(state.clear_index(&[1]));
+        assert!(!state.clear_index(&[0, 1]));
+        assert_eq!(state.keys(), vec![vec![0], vec![1]]);
+        assert_eq!(state.rows(), 2);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&1.into())), Some(1));
+        assert_eq!(state.lookup_len(&[1], &KeyType::Single(&"A".into())), None);
+
+        // the tag still maps to the cleared index, so it can be refilled
+        state.mark_filled(vec!["A".into()], Tag(0));
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"A".into())),
+            Some(0)
+        );
+
+        // clearing the first of several full indices leaves the records counted
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+        assert!(state.clear_index(&[0]));
+        assert_eq!(state.rows(), 2);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&1.into())), Some(0));
+    }
+
+    #[test]
+    fn memory_state_iter_index() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+        insert(&mut state, vec![3.into(), "B".into()]);
+
+        let mut sizes: Vec<_> = state.iter_index(&[1]).unwrap().map(|rs| rs.len()).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![1, 2]);
+        assert_eq!(state.iter_index(&[0]).unwrap().count(), 3);
+        assert!(state.iter_index(&[0, 1]).is_none());
+    }
+
+    #[test]
+    fn memory_state_peek() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+        state.add_lazy_key(&[1]);
+
+        match state.peek(&[1], &KeyType::Single(&"A".into())) {
+            LookupResult::Some(rows) => assert_eq!(rows.len(), 2),
+            LookupResult::Missing => unreachable!(),
+        };
+        assert!(state.lazy[0].1.borrow().is_none());
+
+        match state.peek(&[0], &KeyType::Single(&1.into())) {
+            LookupResult::Some(rows) => assert_eq!(rows.len(), 1),
+            LookupResult::Missing => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn memory_state_remove_key() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![1.into(), "B".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+
+        let mut removed = state.remove_key(&[0], &KeyType::Single(&1.into()));
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![vec![1.into(), "A".into()], vec![1.into(), "B".into()]]
+        );
+        assert_eq!(state.rows(), 1);
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"A".into())),
+            Some(1)
+        );
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"B".into())),
+            Some(0)
+        );
+        assert!(state
+            .remove_key(&[0], &KeyType::Single(&3.into()))
+            .is_empty());
+    }
+
+    #[test]
+    fn memory_state_contains_key() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        state.mark_filled(vec![2.into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into()]);
+        assert!(state.contains_key(&[0], &KeyType::Single(&1.into())));
+        assert!(state.contains_key(&[0], &KeyType::Single(&2.into())));
+        assert!(!state.contains_key(&[0], &KeyType::Single(&3.into())));
+
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), "B".into()]);
+        state.process_records(&mut vec![(vec![2.into(), "B".into()], false)].into(), None);
+        assert!(state.contains_key(&[0], &KeyType::Single(&1.into())));
+        assert!(!state.contains_key(&[0], &KeyType::Single(&2.into())));
+        assert!(!state.contains_key(&[0], &KeyType::Single(&3.into())));
+    }
+
+    #[test]
+    fn memory_state_stats() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        state.mark_filled(vec![2.into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into()]);
+
+        state.lookup(&[0], &KeyType::Single(&1.into()));
+        state.lookup(&[0], &KeyType::Single(&2.into()));
+        state.lookup(&[0], &KeyType::Single(&3.into()));
+        state.lookup(&[0], &KeyType::Single(&3.into()));
+        state.peek(&[0], &KeyType::Single(&1.into()));
+        assert_eq!(
+            state.stats(),
+            LookupStats {
+                missing: 2,
+                empty: 1,
+                hits: 1,
+            }
+        );
+
+        // lookups that only check for a key are counted too
+        state.contains_key(&[0], &KeyType::Single(&1.into()));
+        state.lookup_len(&[0], &KeyType::Single(&3.into()));
+        assert_eq!(
+            state.stats(),
+            LookupStats {
+                missing: 3,
+                empty: 1,
+                hits: 2,
+            }
+        );
+
+        state.clear();
+        assert_eq!(state.stats(), LookupStats::default());
+    }
+
+    #[test]
+    fn memory_state_dump() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0, 1], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into(), "A".into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into(), 2.into()]);
+        insert(&mut state, vec![1.into(), "A".into(), 3.into()]);
+        state.mark_filled(vec![2.into(), "B".into()], Tag(0));
+
+        let mut out = Vec::new();
+        state.dump(&mut out, &[0, 1]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1, \"A\" => [1, \"A\", 2] [1, \"A\", 3]\n2, \"B\" =>\n"
+        );
+    }
+
+    #[test]
+    fn memory_state_shrink_to_fit() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        for i in 0..100 {
+            insert(&mut state, vec![i.into(), (i % 2).into()]);
+        }
+        let mut deletes: Records = (0..90)
+            .map(|i| (vec![i.into(), (i % 2).into()], false))
+            .collect::<Vec<_>>()
+            .into();
+        state.process_records(&mut deletes, None);
+
+        assert!(state.shrink_to_fit() > 0);
+        assert_eq!(state.lookup_len(&[1], &KeyType::Single(&0.into())), Some(5));
+        assert_eq!(
+            state.lookup_len(&[0], &KeyType::Single(&95.into())),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn memory_state_evict_older_than() {
+        use std::thread;
+
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.track_insert_times();
+        state.mark_filled(vec![1.into()], Tag(0));
+        insert(&mut state, vec![1.into(), "A".into()]);
+        thread::sleep(Duration::from_millis(20));
+        state.mark_filled(vec![2.into()], Tag(0));
+
+        assert!(state.row_age(&[0], &[1.into()]).unwrap() >= Duration::from_millis(20));
+        let (evicted, bytes) = state.evict_older_than(Duration::from_millis(10));
+        assert_eq!(evicted, vec![(vec![0], vec![vec![1.into()]])]);
+        assert!(bytes > 0);
+        assert_eq!(state.row_age(&[0], &[1.into()]), None);
+        assert!(state.row_age(&[0], &[2.into()]).is_some());
+    }
+
+    #[test]
+    fn memory_state_evict_range() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        for ts in 1..=4 {
+            state.mark_filled(vec![ts.into()], Tag(0));
+            insert(&mut state, vec![ts.into(), "A".into()]);
+        }
+
+        let (mut evicted, bytes) = state.evict_range(&[0], &[3.into()]);
+        evicted.sort();
+        assert_eq!(evicted, vec![vec![1.into()], vec![2.into()]]);
+        assert!(bytes > 0);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&1.into())), None);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&2.into())), None);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&3.into())), Some(1));
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&4.into())), Some(1));
+    }
+
+    #[test]
+    fn memory_state_min_residency() {
+        use std::thread;
+
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.set_min_residency(Duration::from_millis(20));
+        state.mark_filled(vec![1.into()], Tag(0));
+        thread::sleep(Duration::from_millis(30));
+        state.mark_filled(vec![2.into()], Tag(0));
+
+        assert_eq!(state.evictable_keys(Tag(0)), vec![vec![1.into()]]);
+        let (_, evicted, _) = state.evict_random_keys(2);
+        assert_eq!(evicted, vec![vec![1.into()]]);
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&2.into())), Some(0));
+    }
+
+    #[test]
+    fn memory_state_diff() {
+        let mut before = MemoryState::default();
+        before.add_key(&[0], None);
+        insert(&mut before, vec![1.into(), "A".into()]);
+        insert(&mut before, vec![2.into(), "B".into()]);
+
+        let mut after = MemoryState::default();
+        after.add_key(&[1], None);
+        insert(&mut after, vec![2.into(), "B".into()]);
+        insert(&mut after, vec![3.into(), "C".into()]);
+        insert(&mut after, vec![3.into(), "C".into()]);
+
+        let (added, removed) = after.diff(&before);
+        assert_eq!(added, vec![vec![3.into(), "C".into()]; 2]);
+        assert_eq!(removed, vec![vec![1.into(), "A".into()]]);
+    }
+
+    #[test]
+    fn memory_state_retain_keys() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        for i in 0..4 {
+            insert(&mut state, vec![i.into(), (i % 2).into()]);
+        }
+
+        let keep: HashSet<_> = vec![vec![0.into()]].into_iter().collect();
+        let mut dropped = state.retain_keys(&[1], &keep);
+        dropped.sort();
+        assert_eq!(dropped, vec![vec![1.into()]]);
+
+        // rows under dropped keys are removed from every index
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&1.into())), Some(0));
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&2.into())), Some(1));
+        assert_eq!(state.lookup_len(&[1], &KeyType::Single(&0.into())), Some(2));
+    }
+
+    #[test]
+    fn memory_state_iter_buckets() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0, 1], None);
+        insert(&mut state, vec![1.into(), "a".into(), 1.into()]);
+        insert(&mut state, vec![1.into(), "a".into(), 2.into()]);
+        insert(&mut state, vec![2.into(), "b".into(), 3.into()]);
+
+        let mut buckets: Vec<_> = state
+            .iter_buckets(&[0, 1])
+            .map(|(k, rs)| (k, rs.len()))
+            .collect();
+        buckets.sort();
+        assert_eq!(
+            buckets,
+            vec![
+                (vec![1.into(), "a".into()], 2),
+                (vec![2.into(), "b".into()], 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_state_bucket_bytes() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], Some(vec![Tag(0)]));
+        let short: Vec<DataType> = vec![1.into(), "a".into()];
+        let long: Vec<DataType> = vec![1.into(), "a much longer text value".into()];
+        insert(&mut state, short.clone());
+        insert(&mut state, long.clone());
+
+        assert_eq!(
+            state.bucket_bytes(&[0], &KeyType::Single(&1.into())),
+            Some(short.deep_size_of() + long.deep_size_of())
+        );
+        assert_eq!(
+            state.bucket_bytes(&[0], &KeyType::Single(&2.into())),
+            Some(0)
+        );
+        assert_eq!(
+            state.bucket_bytes(&[1], &KeyType::Single(&"a".into())),
+            None
+        );
+    }
+
+    #[test]
+    fn memory_state_remove_compound_key() {
+        let long = "a key long enough to be stored out of line";
+        let mut state = MemoryState::default();
+        state.add_key(&[0, 1], None);
+        state.add_key(&[1, 2, 0], None);
+        insert(&mut state, vec![long.into(), "A".into(), 1.into()]);
+        insert(&mut state, vec![long.into(), "A".into(), 2.into()]);
+
+        let mut records: Records = vec![(vec![long.into(), "A".into(), 1.into()], false)].into();
+        state.process_records(&mut records, None);
+
+        let (k0, k1) = (DataType::from(long), DataType::from("A"));
+        assert_eq!(
+            state.lookup_len(&[0, 1], &KeyType::Double((k0, k1))),
+            Some(1)
+        );
+        assert_eq!(
+            state.lookup_len(
+                &[1, 2, 0],
+                &KeyType::from(&["A".into(), 1.into(), long.into()])
+            ),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn memory_state_insert_into_holes() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.add_key(&[1], Some(vec![Tag(1)]));
+
+        // neither index has the key filled, so the record is neither kept nor forwarded
+        assert!(!state.insert(vec![1.into(), "A".into()], None));
+        let mut records: Records = vec![(vec![1.into(), "A".into()], true)].into();
+        state.process_records(&mut records, None);
+        assert!(records.is_empty());
+        assert_eq!(state.rows(), 0);
+
+        // once one index has the key, the record is kept there
+        state.mark_filled(vec!["A".into()], Tag(1));
+        assert!(state.insert(vec![1.into(), "A".into()], None));
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"A".into())),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn memory_state_rows() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        insert(&mut state, vec![2.into(), "A".into()]);
+        assert_eq!(state.rows(), 2);
+        assert_eq!(state.rows(), state.cloned_records().len());
+
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        let mut records: Records = vec![
+            (vec![1.into(), "A".into()], true),
+            (vec![2.into(), "A".into()], true),
+        ]
+        .into();
+        state.process_records(&mut records, Some(Tag(0)));
+        assert_eq!(state.rows(), 1);
+        assert_eq!(
+            state.deep_size_of(),
+            Row::from(Rc::new(vec![1.into(), "A".into()])).deep_size_of()
+        );
+
+        // partial indices filled with disjoint records
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.add_key(&[1], Some(vec![Tag(1)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        state.mark_filled(vec!["B".into()], Tag(1));
+        let mut records: Records = vec![
+            (vec![1.into(), "A".into()], true),
+            (vec![2.into(), "B".into()], true),
+            (vec![3.into(), "C".into()], true),
+        ]
+        .into();
+        state.process_records(&mut records, None);
+        assert_eq!(state.rows(), 2);
+
+        // a record held by both indices is only counted once
+        state.mark_filled(vec![4.into()], Tag(0));
+        state.mark_filled(vec!["D".into()], Tag(1));
+        insert(&mut state, vec![4.into(), "D".into()]);
+        assert_eq!(state.rows(), 3);
+    }
+
+    #[test]
+    fn memory_state_insert_many() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        let rows: Vec<Vec<DataType>> = vec![
+            vec![1.into(), "A".into()],
+            vec![2.into(), "A".into()],
+            vec![3.into(), "B".into()],
+        ];
+        assert_eq!(state.insert_many(rows.clone(), None), 3);
+        assert_eq!(state.rows(), 3);
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"A".into())),
+            Some(2)
+        );
+
+        let mut loaded = MemoryState::default();
+        loaded.add_key(&[0], None);
+        loaded.add_key(&[1], None);
+        for row in rows {
+            insert(&mut loaded, row);
+        }
+        assert_eq!(state.deep_size_of(), loaded.deep_size_of());
+
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![Tag(0)]));
+        state.mark_filled(vec![1.into()], Tag(0));
+        let rows = vec![vec![1.into(), "A".into()], vec![2.into(), "A".into()]];
+        assert_eq!(state.insert_many(rows, Some(Tag(0))), 1);
+        assert_eq!(state.rows(), 1);
+    }
+
+    #[test]
+    fn memory_state_dedup() {
+        let mut state = MemoryState::default();
+        let row: Vec<DataType> = vec![10.into(), "Cat".into()];
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, row.clone());
+        insert(&mut state, row.clone());
+        insert(&mut state, row.clone());
+        insert(&mut state, vec![10.into(), "Dog".into()]);
+
+        assert_eq!(state.dedup(), 2);
+        assert_eq!(state.rows(), 2);
+        assert_eq!(
+            state.lookup_len(&[0], &KeyType::Single(&10.into())),
+            Some(2)
+        );
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"Cat".into())),
+            Some(1)
+        );
+        assert_eq!(state.dedup(), 0);
+    }
+
+    #[test]
+    fn memory_state_into_parts() {
+        let mut state = MemoryState::default();
+        let first: Vec<DataType> = vec![10.into(), "Cat".into()];
+        let second: Vec<DataType> = vec![20.into(), "Dog".into()];
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, first.clone());
+        insert(&mut state, second.clone());
+
+        let (keys, mut rows) = Box::new(state).into_parts();
+        rows.sort();
+        assert_eq!(keys, vec![vec![0], vec![1]]);
+        assert_eq!(rows, vec![first, second]);
+    }
+
+    #[test]
+    fn memory_state_into_keyed_iter() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![10.into(), "Cat".into()]);
+        insert(&mut state, vec![10.into(), "Dog".into()]);
+        insert(&mut state, vec![20.into(), "Cat".into()]);
+
+        let mut buckets: Vec<_> = Box::new(state).into_keyed_iter().collect();
+        buckets.sort();
+        for (_, rows) in &mut buckets {
+            rows.sort();
+        }
+        assert_eq!(
+            buckets,
+            vec![
+                (
+                    vec![10.into()],
+                    vec![vec![10.into(), "Cat".into()], vec![10.into(), "Dog".into()]]
+                ),
+                (vec![20.into()], vec![vec![20.into(), "Cat".into()]]),
+            ]
+        );
+    }
 }
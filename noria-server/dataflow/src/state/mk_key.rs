@@ -1,3 +1,7 @@
+use common::DataType;
+use indexmap::Equivalent;
+use std::hash::{Hash, Hasher};
+
 pub(super) trait MakeKey<A> {
     fn from_row(key: &[usize], row: &[A]) -> Self;
     fn from_key(key: &[A]) -> Self;
@@ -107,3 +111,56 @@ impl<A: Clone> MakeKey<A> for (A, A, A, A, A, A) {
         )
     }
 }
+
+impl<A: Clone> MakeKey<A> for Vec<A> {
+    #[inline(always)]
+    fn from_row(key: &[usize], row: &[A]) -> Self {
+        key.iter().map(|&c| row[c].clone()).collect()
+    }
+    #[inline(always)]
+    fn from_key(key: &[A]) -> Self {
+        key.to_vec()
+    }
+}
+
+/// The key columns of a row, borrowed from the row so that a map keyed by tuples of their values
+/// can be probed without cloning the values.
+pub(super) struct RowKey<'a> {
+    key: &'a [usize],
+    row: &'a [DataType],
+}
+
+impl<'a> RowKey<'a> {
+    #[inline(always)]
+    pub(super) fn new(key: &'a [usize], row: &'a [DataType]) -> Self {
+        RowKey { key, row }
+    }
+}
+
+impl<'a> Hash for RowKey<'a> {
+    #[inline(always)]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // tuples hash each of their fields in order, so this hashes the same as the owned key
+        for &c in self.key {
+            self.row[c].hash(state);
+        }
+    }
+}
+
+macro_rules! row_key_equivalent {
+    ($t:ty; $($i:tt)+) => {
+        impl<'a> Equivalent<$t> for RowKey<'a> {
+            #[inline(always)]
+            fn equivalent(&self, other: &$t) -> bool {
+                debug_assert_eq!(self.key.len(), [$($i),+].len());
+                $(self.row[self.key[$i]] == other.$i)&&+
+            }
+        }
+    };
+}
+
+row_key_equivalent!((DataType, DataType); 0 1);
+row_key_equivalent!((DataType, DataType, DataType); 0 1 2);
+row_key_equivalent!((DataType, DataType, DataType, DataType); 0 1 2 3);
+row_key_equivalent!((DataType, DataType, DataType, DataType, DataType); 0 1 2 3 4);
+row_key_equivalent!((DataType, DataType, DataType, DataType, DataType, DataType); 0 1 2 3 4 5);
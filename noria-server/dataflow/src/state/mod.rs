@@ -1,3 +1,4 @@
+mod hll;
 mod keyed_state;
 mod memory_state;
 mod mk_key;
@@ -5,12 +6,16 @@ mod persistent_state;
 mod single_state;
 
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::ops::Deref;
 use std::rc::Rc;
+use std::time::Duration;
 use std::{slice, vec};
 
 use crate::prelude::*;
 use common::SizeOf;
+use itertools::Itertools;
 
 pub(crate) use self::memory_state::MemoryState;
 pub(crate) use self::persistent_state::PersistentState;
@@ -19,6 +24,20 @@ pub(crate) trait State: SizeOf + Send {
     /// Add an index keyed by the given columns and replayed to by the given partial tags.
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>);
 
+    /// Register an index keyed by the given columns that is only built when it is first looked
+    /// up, and is maintained on writes from then on.
+    ///
+    /// This makes writes cheaper for indices that are rarely queried, at the cost of a full scan
+    /// on the first lookup. Panics if the state is only partially materialized.
+    fn add_lazy_key(&mut self, columns: &[usize]);
+
+    /// Change the key columns of the index keyed by `old_columns` to `new_columns`.
+    ///
+    /// A fully materialized index is rebuilt from the records it holds. A partial index keeps its
+    /// tags, but starts out with every key a hole, since keys filled under the old columns say
+    /// nothing about which keys are filled under the new ones.
+    fn reindex(&mut self, old_columns: &[usize], new_columns: &[usize]);
+
     /// Returns whether this state is currently keyed on anything. If not, then it cannot store any
     /// infromation and is thus "not useful".
     fn is_useful(&self) -> bool;
@@ -29,19 +48,378 @@ pub(crate) trait State: SizeOf + Send {
     // are removed from `records` (thus the mutable reference).
     fn process_records(&mut self, records: &mut Records, partial_tag: Option<Tag>);
 
+    /// Insert all of `rows` at once, returning how many of them hit at least one index.
+    ///
+    /// This is meant for bulk loading, e.g. when a base table is first populated, and lets the
+    /// state avoid the per-record overhead of going through [`State::process_records`].
+    fn insert_many(&mut self, rows: Vec<Vec<DataType>>, partial_tag: Option<Tag>) -> usize {
+        let mut records: Records = rows
+            .into_iter()
+            .map(|r| (r, true))
+            .collect::<Vec<_>>()
+            .into();
+        self.process_records(&mut records, partial_tag);
+        records.len()
+    }
+
+    /// Remove every record stored under `key` in the index keyed by `columns` from this state,
+    /// returning the removed records.
+    ///
+    /// The records are removed from every index, not just the one they are found through. If the
+    /// key is a hole in a partial index, nothing is known about its records, and nothing is
+    /// removed.
+    fn remove_key(&mut self, columns: &[usize], key: &KeyType) -> Vec<Vec<DataType>> {
+        let rows: Vec<Vec<DataType>> = match self.lookup(columns, key) {
+            LookupResult::Some(rs) => rs.into_iter().map(Cow::into_owned).collect(),
+            LookupResult::Missing => return Vec::new(),
+        };
+        let mut records: Records = rows
+            .iter()
+            .map(|r| (r.clone(), false))
+            .collect::<Vec<_>>()
+            .into();
+        self.process_records(&mut records, None);
+        rows
+    }
+
+    /// Mark the given key as a hole in the index targeted by `tag`.
+    ///
+    /// Note that several tags may be replayed into the same index, in which case the key becomes
+    /// a hole for all of them. See [`State::evictable_keys`].
     fn mark_hole(&mut self, key: &[DataType], tag: Tag);
 
     fn mark_filled(&mut self, key: Vec<DataType>, tag: Tag);
 
+    /// Mark all the given keys as filled in the index targeted by `tag`.
+    fn mark_filled_batch(&mut self, keys: Vec<Vec<DataType>>, tag: Tag);
+
     fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a>;
 
+    /// Look up `key` like [`State::lookup`], but without any side effects on the state.
+    ///
+    /// This is meant for diagnostics. It never builds a lazy index, and must not count as an
+    /// access to the key in any eviction bookkeeping.
+    fn peek<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a> {
+        self.lookup(columns, key)
+    }
+
+    /// Look up each of the given keys in the index keyed by `columns`, returning the records found
+    /// for keys that are present, along with the keys that are holes in this (partial) state.
+    fn lookup_in<'a>(
+        &'a self,
+        columns: &[usize],
+        keys: &[Vec<DataType>],
+    ) -> (Vec<RecordResult<'a>>, Vec<Vec<DataType>>) {
+        let mut hits = Vec::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        for key in keys {
+            match self.lookup(columns, &KeyType::from(&key[..])) {
+                LookupResult::Some(rs) => hits.push(rs),
+                LookupResult::Missing => misses.push(key.clone()),
+            }
+        }
+        (hits, misses)
+    }
+
+    /// Look up `key` in the index keyed by `columns`, and if it is a hole, fill it for `tag` with
+    /// the records computed by `f` first.
+    ///
+    /// `f` is only called on a miss.
+    fn get_or_insert_with<'a>(
+        &'a mut self,
+        columns: &[usize],
+        tag: Tag,
+        key: &[DataType],
+        f: &mut dyn FnMut() -> Vec<Vec<DataType>>,
+    ) -> RecordResult<'a> {
+        if self.lookup_len(columns, &KeyType::from(key)).is_none() {
+            self.mark_filled(key.to_vec(), tag);
+            let mut records: Records = f().into_iter().collect();
+            self.process_records(&mut records, Some(tag));
+        }
+
+        match self.lookup(columns, &KeyType::from(key)) {
+            LookupResult::Some(rs) => rs,
+            LookupResult::Missing => unreachable!("key was just filled"),
+        }
+    }
+
+    /// Look up each of `keys` in the index keyed by `columns`, returning one result per key.
+    ///
+    /// As with [`State::lookup`], a key that is a hole in a partial index yields
+    /// `LookupResult::Missing`, while a filled key with no records yields an empty result.
+    fn lookup_many<'a>(&'a self, columns: &[usize], keys: &[KeyType]) -> Vec<LookupResult<'a>> {
+        keys.iter().map(|key| self.lookup(columns, key)).collect()
+    }
+
+    /// Look up each key in the index keyed by the columns it is paired with, returning one result
+    /// per key.
+    ///
+    /// This is a diagnostic: keys that identify the same records should yield the same records
+    /// from every index, and any difference points at indices that have fallen out of sync.
+    fn lookup_all_indices<'a>(&'a self, keys: &[(Vec<usize>, KeyType)]) -> Vec<LookupResult<'a>> {
+        keys.iter()
+            .map(|(columns, key)| self.lookup(columns, key))
+            .collect()
+    }
+
+    /// Return the number of records stored for the given key, or `None` if the key is a hole in
+    /// this (partial) state.
+    fn lookup_len(&self, columns: &[usize], key: &KeyType) -> Option<usize> {
+        match self.lookup(columns, key) {
+            LookupResult::Some(rs) => Some(rs.len()),
+            LookupResult::Missing => None,
+        }
+    }
+
+    /// Returns whether `key` is present in the index keyed by `columns`, without handing out its
+    /// records.
+    ///
+    /// In a partial index, a key is present if it is filled, even if it holds no records. A full
+    /// index has no holes, so there a key is present if any records are stored under it.
+    fn contains_key(&self, columns: &[usize], key: &KeyType) -> bool {
+        self.lookup_len(columns, key).map_or(false, |n| n > 0)
+    }
+
+    /// Iterate over every key in the index keyed by `columns` along with the records stored
+    /// under that key. Holes in a partial index are skipped.
+    fn iter_buckets<'a>(
+        &'a self,
+        columns: &[usize],
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, RecordResult<'a>)> + 'a>;
+
+    /// Iterate over the buckets of the index keyed by `columns`, or return `None` if there is no
+    /// such index. Holes in a partial index are skipped.
+    ///
+    /// Only states that keep their records in memory can hand out buckets this way. Other states,
+    /// such as `PersistentState`, always return `None`; use [`State::iter_buckets`] instead.
+    fn iter_index<'a>(
+        &'a self,
+        columns: &[usize],
+    ) -> Option<Box<dyn Iterator<Item = &'a [Row]> + 'a>>;
+
+    /// Iterate over every record in the first index of this state, whatever its key arity.
+    ///
+    /// Holes in a partial index are skipped, so only the records of filled keys are yielded.
+    /// States that don't keep their records in memory yield owned copies.
+    fn iter_rows<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, [DataType]>> + 'a> {
+        match self.keys().first() {
+            Some(columns) => Box::new(
+                self.iter_index(columns)
+                    .expect("first index of state is missing")
+                    .flat_map(<[Row]>::iter)
+                    .map(|r| Cow::Borrowed(&r[..])),
+            ),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// Return every record in the index keyed by `columns` whose leading key columns equal
+    /// `prefix`.
+    ///
+    /// Indices are hash-based, so this scans every bucket of the index. Holes in a partial index
+    /// are skipped, which means the result may be incomplete for a partial index.
+    fn lookup_prefix<'a>(&'a self, columns: &[usize], prefix: &[DataType]) -> RecordResult<'a> {
+        assert!(prefix.len() <= columns.len());
+        RecordResult::Owned(
+            self.iter_buckets(columns)
+                .filter(|(key, _)| key.starts_with(prefix))
+                .flat_map(|(_, rs)| rs.into_iter().map(Cow::into_owned))
+                .collect(),
+        )
+    }
+
+    /// Start maintaining the smallest and largest value of column `col` in each bucket of the
+    /// index keyed by `columns`, for [`State::bucket_min`] and [`State::bucket_max`].
+    ///
+    /// The extremes stay correct when the current minimum or maximum of a bucket is removed. States
+    /// that cannot track extremes scan the bucket instead.
+    ///
+    /// Only one column can be tracked per index, and asking to track a second column panics.
+    /// Tracking does not survive [`State::reindex`]: the rebuilt index scans its buckets again
+    /// until tracking is restarted. Lazy indices cannot be tracked until they have been built.
+    fn track_bucket_extremes(&mut self, columns: &[usize], col: usize);
+
+    /// Return the smallest value of column `col` in the bucket for `key` in the index keyed by
+    /// `columns`, or `None` if the bucket is empty or a hole.
+    ///
+    /// Whether or not the column is tracked, this reads the bucket like [`State::peek`], so it is
+    /// not counted in the lookup stats and does not protect the key from eviction.
+    fn bucket_min(&self, columns: &[usize], col: usize, key: &[DataType]) -> Option<DataType> {
+        bucket_extreme(self.peek(columns, &KeyType::from(key)), col, false)
+    }
+
+    /// Return the largest value of column `col` in the bucket for `key` in the index keyed by
+    /// `columns`, or `None` if the bucket is empty or a hole.
+    fn bucket_max(&self, columns: &[usize], col: usize, key: &[DataType]) -> Option<DataType> {
+        bucket_extreme(self.peek(columns, &KeyType::from(key)), col, true)
+    }
+
+    /// Return one arbitrary record from each non-empty bucket of the index keyed by `columns`.
+    ///
+    /// Only a single record is cloned per key, which makes this much cheaper than cloning all
+    /// records and deduplicating them when buckets hold many records.
+    fn one_per_key(&self, columns: &[usize]) -> Vec<Vec<DataType>> {
+        self.iter_buckets(columns)
+            .filter_map(|(_, rs)| rs.into_iter().next().map(Cow::into_owned))
+            .collect()
+    }
+
+    /// Write the contents of the index keyed by `columns` to `w` in a human-readable form, with
+    /// one line per key listing the key followed by each of its records.
+    ///
+    /// This is meant for debugging, and the output format should not be relied upon. Holes in a
+    /// partial index are skipped.
+    fn dump(&self, w: &mut dyn io::Write, columns: &[usize]) -> io::Result<()> {
+        for (key, rs) in self.iter_buckets(columns) {
+            write!(w, "{} =>", key.iter().join(", "))?;
+            for r in rs {
+                write!(w, " [{}]", r.iter().join(", "))?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Return the approximate number of bytes held by the records stored under the given key, or
+    /// `None` if the key is a hole in this (partial) state.
+    ///
+    /// Records that are shared with other indices are counted in full.
+    fn bucket_bytes(&self, columns: &[usize], key: &KeyType) -> Option<u64> {
+        match self.lookup(columns, key) {
+            LookupResult::Some(RecordResult::Borrowed(rs)) => {
+                Some(rs.iter().map(SizeOf::deep_size_of).sum())
+            }
+            LookupResult::Some(RecordResult::Owned(rs)) => {
+                Some(rs.iter().map(SizeOf::deep_size_of).sum())
+            }
+            LookupResult::Missing => None,
+        }
+    }
+
+    /// Return the number of records stored in this state. A record is counted once, however many
+    /// indices hold it, and records that fell into holes are not counted.
     fn rows(&self) -> usize;
 
     fn keys(&self) -> Vec<Vec<usize>>;
 
+    /// Return the key columns of each index along with the keys currently present in that index.
+    ///
+    /// This is intended for diagnostics, as it copies every key in the state.
+    fn all_keys_across_indices(&self) -> HashMap<Vec<usize>, Vec<Vec<DataType>>>;
+
+    /// For each index, return its key columns, the key that `row` maps to in that index, and
+    /// whether a bucket for that key is currently present.
+    ///
+    /// This is a diagnostic for tracking down records that appear in one index but not another.
+    fn locate(&self, row: &[DataType]) -> Vec<(Vec<usize>, Vec<DataType>, bool)>;
+
     /// Return a copy of all records. Panics if the state is only partially materialized.
     fn cloned_records(&self) -> Vec<Vec<DataType>>;
 
+    /// Compare the records in this state against those in `other`, returning the records that are
+    /// only in this state (added) and those that are only in `other` (removed).
+    ///
+    /// Duplicate records are compared by count. This copies all records of both states, and is
+    /// intended for validating migrations and in tests. Panics if either state is only partially
+    /// materialized.
+    fn diff(&self, other: &dyn State) -> (Vec<Vec<DataType>>, Vec<Vec<DataType>>) {
+        let mut counts: HashMap<Vec<DataType>, isize> = HashMap::new();
+        for r in self.cloned_records() {
+            *counts.entry(r).or_insert(0) += 1;
+        }
+        for r in other.cloned_records() {
+            *counts.entry(r).or_insert(0) -= 1;
+        }
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for (r, n) in counts {
+            if n > 0 {
+                added.extend(std::iter::repeat(r).take(n as usize));
+            } else if n < 0 {
+                removed.extend(std::iter::repeat(r).take(-n as usize));
+            }
+        }
+        (added, removed)
+    }
+
+    /// Compute an equi-depth histogram with at most `buckets` buckets over the values of column
+    /// `col`, returning the lowest value, highest value, and number of values in each bucket.
+    ///
+    /// This performs a full scan of the state, and is intended to be computed periodically (e.g.,
+    /// for query planning statistics), not on every query. Panics if the state is only partially
+    /// materialized.
+    fn column_histogram(&self, col: usize, buckets: usize) -> Vec<(DataType, DataType, usize)>;
+
+    /// Sum all non-NULL values of column `col`, or return `None` if the column holds
+    /// non-numeric values.
+    ///
+    /// The sum is an integer unless the column holds any real values. This performs a full scan
+    /// of the state. Panics if the state is only partially materialized.
+    fn sum_column(&self, col: usize) -> Option<DataType>;
+
+    /// Start maintaining an estimate of the number of distinct values in column `col`, which is
+    /// needed for [`State::approx_count_distinct`].
+    ///
+    /// The estimate is seeded from the records already in the state, and then updated as records
+    /// are inserted. Removed records are not taken out of the estimate.
+    fn track_distinct(&mut self, col: usize);
+
+    /// Return an estimate of the number of distinct values in column `col`, or `None` if the
+    /// column is not tracked (see [`State::track_distinct`]).
+    ///
+    /// This takes constant time, and the estimate is typically within a few percent.
+    fn approx_count_distinct(&self, col: usize) -> Option<u64>;
+
+    /// Start recording when each key in every index is filled or inserted into, which is needed
+    /// for [`State::row_age`] and [`State::evict_older_than`].
+    fn track_insert_times(&mut self);
+
+    /// Return how long ago the given key was last filled or inserted into in the index keyed by
+    /// `columns`, or `None` if that is not known or the key is a hole.
+    fn row_age(&self, columns: &[usize], key: &[DataType]) -> Option<Duration>;
+
+    /// Evict every key that was last filled or inserted into more than `age` ago from each
+    /// partial index, returning the key columns and evicted keys of each index along with the
+    /// number of bytes evicted.
+    ///
+    /// Keys in fully materialized indices are not evicted. Requires that insert times are tracked
+    /// (see [`State::track_insert_times`]).
+    fn evict_older_than(&mut self, age: Duration) -> (Vec<(Vec<usize>, Vec<Vec<DataType>>)>, u64);
+
+    /// Start tracking when each key of every partial index was last looked up or filled, for
+    /// [`State::evict_lru`].
+    fn track_access_times(&mut self);
+
+    /// Evict the least recently looked up or filled keys from partial indices until at least
+    /// `bytes_to_free` bytes have been freed or no keys are left, returning the key columns and
+    /// evicted keys of each index along with the number of bytes evicted.
+    ///
+    /// Keys in fully materialized indices are never evicted. Requires that access times are
+    /// tracked (see [`State::track_access_times`]).
+    fn evict_lru(&mut self, bytes_to_free: u64) -> (Vec<(Vec<usize>, Vec<Vec<DataType>>)>, u64);
+
+    /// Evict every key below `upper_bound` from the partial index keyed by `columns`, returning
+    /// the evicted keys and the number of bytes evicted.
+    ///
+    /// Keys are compared column by column, so this suits indices whose leading column is
+    /// monotonic, such as a timestamp in a sliding-window materialization.
+    fn evict_range(
+        &mut self,
+        columns: &[usize],
+        upper_bound: &[DataType],
+    ) -> (Vec<Vec<DataType>>, u64);
+
+    /// Protect keys that were filled or inserted into less than `min_residency` ago from
+    /// eviction under memory pressure, so that keys that were just replayed are not immediately
+    /// evicted again.
+    ///
+    /// This applies to [`State::evict_random_keys`], [`State::evictable_keys`] and
+    /// [`State::evict_older_than`], but not to [`State::evict_keys`], since keys evicted upstream
+    /// must also be evicted here. Insert times are tracked from this point on.
+    fn set_min_residency(&mut self, min_residency: Duration);
+
     /// Evict `count` randomly selected keys, returning key colunms of the index chosen to evict
     /// from along with the keys evicted and the number of bytes evicted.
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64);
@@ -50,7 +428,144 @@ pub(crate) trait State: SizeOf + Send {
     /// of the index that was evicted from and the number of bytes evicted.
     fn evict_keys(&mut self, tag: Tag, keys: &[Vec<DataType>]) -> Option<(&[usize], u64)>;
 
+    /// Return the keys that can be evicted on behalf of `tag` without affecting other replay
+    /// paths.
+    ///
+    /// Evictions apply to an index, not to a tag, so evicting a key from an index that is shared
+    /// by several tags holes that key for every one of them. To keep one replay path from
+    /// evicting state that another relies on, no keys are returned if the index targeted by `tag`
+    /// is shared, or if `tag` is not known to this state. States that are never partial, such as
+    /// `PersistentState`, never return any keys.
+    fn evictable_keys(&self, tag: Tag) -> Vec<Vec<DataType>>;
+
+    /// Drop every key not in `keep` from the index keyed by `columns`, returning the dropped keys.
+    ///
+    /// Dropped keys become holes if the index is partial. Otherwise, the records stored under them
+    /// are removed from the state entirely.
+    fn retain_keys(
+        &mut self,
+        columns: &[usize],
+        keep: &HashSet<Vec<DataType>>,
+    ) -> Vec<Vec<DataType>>;
+
+    /// Remove every copy of the record `r`, returning the number of copies removed.
+    ///
+    /// A negative record passed to [`State::process_records`] only removes a single copy of a
+    /// record, which leaves any duplicates behind.
+    fn remove_all_matching(&mut self, r: &[DataType]) -> usize;
+
+    /// Remove exact duplicate records, keeping one copy of each, and return the number of
+    /// records removed.
+    ///
+    /// This is a repair tool for state that has accumulated duplicates, and scans the entire
+    /// state. Panics if the state is only partially materialized.
+    fn dedup(&mut self) -> usize;
+
+    /// Walk every index of this state and return the approximate number of bytes it holds,
+    /// including keys, buckets, and records.
+    ///
+    /// Unlike `deep_size_of`, which is maintained incrementally and only counts records, this
+    /// also counts the overhead of each index. Records shared between indices are counted once.
+    fn footprint(&self) -> u64;
+
+    /// Release memory that indices hold on to after many records have been removed, returning the
+    /// approximate number of bytes released.
+    ///
+    /// Since this only releases unused capacity, it does not change `deep_size_of`.
+    fn shrink_to_fit(&mut self) -> u64;
+
+    /// Return how the lookups into this state have turned out since it was last cleared.
+    ///
+    /// This covers lookups made through [`State::lookup`], [`State::lookup_len`] and
+    /// [`State::contains_key`], but peeks are not counted. A high share of missing lookups in a
+    /// partial state means many of its reads trigger replays.
+    fn stats(&self) -> LookupStats;
+
     fn clear(&mut self);
+
+    /// Empty the index keyed by `columns` while keeping it, and any tags that map to it,
+    /// registered. The other indices are left untouched. Returns whether there was such an index.
+    ///
+    /// A cleared full index no longer agrees with the other indices until it is refilled. Since
+    /// [`State::rows`] counts each record once across all indices, records that the other indices
+    /// still hold keep being counted.
+    fn clear_index(&mut self, columns: &[usize]) -> bool;
+
+    /// Consume this state, returning the key columns of each index along with all stored records.
+    /// Panics if the state is only partially materialized.
+    fn into_parts(self: Box<Self>) -> (Vec<Vec<usize>>, Vec<Vec<DataType>>);
+
+    /// Consume this state, yielding each key of the first index along with the records stored
+    /// under that key. Holes in a partial index are skipped.
+    ///
+    /// Records that are not shared with anything outside of this state are moved out rather than
+    /// cloned.
+    fn into_keyed_iter(
+        self: Box<Self>,
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, Vec<Vec<DataType>>)>>;
+}
+
+/// Split the sorted `values` into at most `buckets` equally sized (low, high, count) buckets.
+fn equi_depth_histogram(
+    mut values: Vec<DataType>,
+    buckets: usize,
+) -> Vec<(DataType, DataType, usize)> {
+    assert!(buckets > 0, "histogram must have at least one bucket");
+    if values.is_empty() {
+        return Vec::new();
+    }
+
+    values.sort();
+    let per_bucket = (values.len() + buckets - 1) / buckets;
+    values
+        .chunks(per_bucket)
+        .map(|vs| (vs[0].clone(), vs[vs.len() - 1].clone(), vs.len()))
+        .collect()
+}
+
+/// Scan the records of a bucket for the smallest (or largest, if `max` is set) value of column
+/// `col`.
+fn bucket_extreme(rs: LookupResult, col: usize, max: bool) -> Option<DataType> {
+    let values = match rs {
+        LookupResult::Some(rs) => rs.into_iter().map(|r| r[col].clone()),
+        LookupResult::Missing => return None,
+    };
+    if max {
+        values.max()
+    } else {
+        values.min()
+    }
+}
+
+/// Sum the given values, skipping NULLs. Returns `None` if any value is non-numeric.
+fn sum_values<'a, I>(values: I) -> Option<DataType>
+where
+    I: IntoIterator<Item = &'a DataType>,
+{
+    let mut int_sum: i128 = 0;
+    let mut real_sum: Option<f64> = None;
+    for v in values {
+        match *v {
+            DataType::None => {}
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => {
+                let i: i128 = v.into();
+                int_sum += i;
+            }
+            DataType::Real(..) => {
+                let f: f64 = v.into();
+                *real_sum.get_or_insert(0.0) += f;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(match real_sum {
+        Some(f) => (f + int_sum as f64).into(),
+        None => int_sum.into(),
+    })
 }
 
 #[derive(Clone, Debug)]
@@ -133,3 +648,32 @@ pub(crate) enum LookupResult<'a> {
     Some(RecordResult<'a>),
     Missing,
 }
+
+/// How the lookups into a state have turned out since it was created or last cleared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct LookupStats {
+    /// Lookups that hit a hole in a partial index.
+    pub(crate) missing: u64,
+    /// Lookups for a key that is present, but has no records.
+    pub(crate) empty: u64,
+    /// Lookups that found at least one record.
+    pub(crate) hits: u64,
+}
+
+impl LookupStats {
+    fn count(&mut self, result: &LookupResult) {
+        self.count_len(match *result {
+            LookupResult::Some(ref rs) => Some(rs.len()),
+            LookupResult::Missing => None,
+        })
+    }
+
+    /// Count a lookup that only found out how many records are stored under a key, if any.
+    fn count_len(&mut self, len: Option<usize>) {
+        match len {
+            None => self.missing += 1,
+            Some(0) => self.empty += 1,
+            Some(_) => self.hits += 1,
+        }
+    }
+}
@@ -2,10 +2,16 @@ use bincode;
 use itertools::Itertools;
 use rocksdb::{self, PlainTableFactoryOptions, SliceTransform, WriteBatch};
 use serde;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::time::Duration;
 use tempfile::{tempdir, TempDir};
 
 use crate::prelude::*;
-use crate::state::{RecordResult, State};
+use crate::state::hll::HyperLogLog;
+use crate::state::{equi_depth_histogram, sum_values, LookupStats, RecordResult, State};
 use common::SizeOf;
 
 // Incremented on each PersistentState initialization so that IndexSeq
@@ -53,6 +59,10 @@ pub struct PersistentState {
     seq: IndexSeq,
     epoch: IndexEpoch,
     has_unique_index: bool,
+    // Distinct value estimates for the tracked columns. These are kept in memory only, and have
+    // to be tracked again after a restart.
+    distinct: Vec<(usize, HyperLogLog)>,
+    stats: Cell<LookupStats>,
     // With DurabilityMode::DeleteOnExit,
     // RocksDB files are stored in a temporary directory.
     _directory: Option<TempDir>,
@@ -110,7 +120,42 @@ impl State for PersistentState {
                 .collect()
         };
 
-        LookupResult::Some(RecordResult::Owned(data))
+        let rs = LookupResult::Some(RecordResult::Owned(data));
+        let mut stats = self.stats.get();
+        stats.count(&rs);
+        self.stats.set(stats);
+        rs
+    }
+
+    // Counts the matching rows without deserializing any of them.
+    fn lookup_len(&self, columns: &[usize], key: &KeyType) -> Option<usize> {
+        let db = self.db.as_ref().unwrap();
+        let index_id = self
+            .indices
+            .iter()
+            .position(|index| &index.columns[..] == columns)
+            .expect("lookup on non-indexed column set");
+        let cf = db.cf_handle(&self.indices[index_id].column_family).unwrap();
+        let prefix = Self::serialize_prefix(&key);
+        let n = if index_id == 0 && self.has_unique_index {
+            db.get_cf(cf, &prefix).unwrap().map(|_| 1).unwrap_or(0)
+        } else {
+            db.prefix_iterator_cf(cf, &prefix).unwrap().count()
+        };
+
+        let mut stats = self.stats.get();
+        stats.count_len(Some(n));
+        self.stats.set(stats);
+        Some(n)
+    }
+
+    fn add_lazy_key(&mut self, columns: &[usize]) {
+        // indices live on disk, so building them eagerly does not cost memory
+        self.add_key(columns, None);
+    }
+
+    fn reindex(&mut self, _: &[usize], _: &[usize]) {
+        unreachable!("can't reindex PersistentState")
     }
 
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
@@ -159,6 +204,43 @@ impl State for PersistentState {
         self.persist_meta();
     }
 
+    fn iter_buckets<'a>(
+        &'a self,
+        columns: &[usize],
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, RecordResult<'a>)> + 'a> {
+        let mut buckets: HashMap<Vec<DataType>, Vec<Vec<DataType>>> = HashMap::new();
+        for row in self.cloned_records() {
+            let key = columns.iter().map(|&c| row[c].clone()).collect();
+            buckets.entry(key).or_default().push(row);
+        }
+
+        Box::new(
+            buckets
+                .into_iter()
+                .map(|(k, rs)| (k, RecordResult::Owned(rs))),
+        )
+    }
+
+    fn iter_index<'a>(&'a self, _: &[usize]) -> Option<Box<dyn Iterator<Item = &'a [Row]> + 'a>> {
+        // there are no in-memory Rows to borrow from
+        None
+    }
+
+    fn iter_rows<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, [DataType]>> + 'a> {
+        if self.indices.is_empty() {
+            return Box::new(std::iter::empty());
+        }
+
+        Box::new(
+            self.all_rows()
+                .map(|(_, value)| Cow::Owned(bincode::deserialize(&value).unwrap())),
+        )
+    }
+
+    fn track_bucket_extremes(&mut self, _: &[usize], _: usize) {
+        // extremes are found by scanning the bucket, which has to be read from RocksDB anyway
+    }
+
     fn keys(&self) -> Vec<Vec<usize>> {
         self.indices
             .iter()
@@ -166,12 +248,84 @@ impl State for PersistentState {
             .collect()
     }
 
+    fn locate(&self, row: &[DataType]) -> Vec<(Vec<usize>, Vec<DataType>, bool)> {
+        self.indices
+            .iter()
+            .map(|index| {
+                let present =
+                    match self.lookup(&index.columns, &Self::build_key(row, &index.columns)) {
+                        LookupResult::Some(rs) => !rs.is_empty(),
+                        LookupResult::Missing => false,
+                    };
+                let key = index.columns.iter().map(|&c| row[c].clone()).collect();
+                (index.columns.clone(), key, present)
+            })
+            .collect()
+    }
+
+    fn all_keys_across_indices(&self) -> HashMap<Vec<usize>, Vec<Vec<DataType>>> {
+        let rows = self.cloned_records();
+        self.indices
+            .iter()
+            .map(|index| {
+                let keys = rows
+                    .iter()
+                    .map(|r| index.columns.iter().map(|&c| r[c].clone()).collect())
+                    .unique()
+                    .collect();
+                (index.columns.clone(), keys)
+            })
+            .collect()
+    }
+
     fn cloned_records(&self) -> Vec<Vec<DataType>> {
         self.all_rows()
             .map(|(_, ref value)| bincode::deserialize(&value).unwrap())
             .collect()
     }
 
+    fn column_histogram(&self, col: usize, buckets: usize) -> Vec<(DataType, DataType, usize)> {
+        let values = self
+            .all_rows()
+            .map(|(_, ref value)| {
+                let mut row: Vec<DataType> = bincode::deserialize(&value).unwrap();
+                row.swap_remove(col)
+            })
+            .collect();
+        equi_depth_histogram(values, buckets)
+    }
+
+    fn sum_column(&self, col: usize) -> Option<DataType> {
+        let values: Vec<DataType> = self
+            .all_rows()
+            .map(|(_, ref value)| {
+                let mut row: Vec<DataType> = bincode::deserialize(&value).unwrap();
+                row.swap_remove(col)
+            })
+            .collect();
+        sum_values(&values)
+    }
+
+    fn track_distinct(&mut self, col: usize) {
+        if self.distinct.iter().any(|&(c, _)| c == col) {
+            return;
+        }
+
+        let mut hll = HyperLogLog::default();
+        for (_, value) in self.all_rows() {
+            let row: Vec<DataType> = bincode::deserialize(&value).unwrap();
+            hll.insert(&row[col]);
+        }
+        self.distinct.push((col, hll));
+    }
+
+    fn approx_count_distinct(&self, col: usize) -> Option<u64> {
+        self.distinct
+            .iter()
+            .find(|&&(c, _)| c == col)
+            .map(|(_, hll)| hll.estimate())
+    }
+
     // Returns a row count estimate from RocksDB.
     fn rows(&self) -> usize {
         let db = self.db.as_ref().unwrap();
@@ -196,6 +350,10 @@ impl State for PersistentState {
         unreachable!("PersistentState can't be partial")
     }
 
+    fn mark_filled_batch(&mut self, _: Vec<Vec<DataType>>, _: Tag) {
+        unreachable!("PersistentState can't be partial")
+    }
+
     fn mark_hole(&mut self, _: &[DataType], _: Tag) {
         unreachable!("PersistentState can't be partial")
     }
@@ -208,9 +366,116 @@ impl State for PersistentState {
         unreachable!("can't evict keys from PersistentState")
     }
 
+    fn track_insert_times(&mut self) {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn row_age(&self, _: &[usize], _: &[DataType]) -> Option<Duration> {
+        None
+    }
+
+    fn track_access_times(&mut self) {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn evict_lru(&mut self, _: u64) -> (Vec<(Vec<usize>, Vec<Vec<DataType>>)>, u64) {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn set_min_residency(&mut self, _: Duration) {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn evict_older_than(&mut self, _: Duration) -> (Vec<(Vec<usize>, Vec<Vec<DataType>>)>, u64) {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn evict_range(&mut self, _: &[usize], _: &[DataType]) -> (Vec<Vec<DataType>>, u64) {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn evictable_keys(&self, _: Tag) -> Vec<Vec<DataType>> {
+        // PersistentState is never partial, so no tag can evict from it
+        Vec::new()
+    }
+
+    fn retain_keys(&mut self, _: &[usize], _: &HashSet<Vec<DataType>>) -> Vec<Vec<DataType>> {
+        unreachable!("can't evict keys from PersistentState")
+    }
+
+    fn remove_all_matching(&mut self, r: &[DataType]) -> usize {
+        let columns = self.indices[0].columns.clone();
+        let copies = match self.lookup(&columns, &Self::build_key(r, &columns)) {
+            LookupResult::Some(rs) => rs.into_iter().filter(|rr| &rr[..] == r).count(),
+            LookupResult::Missing => 0,
+        };
+
+        // as with dedup, each removal must be written before the next one
+        for _ in 0..copies {
+            let mut batch = WriteBatch::default();
+            self.remove(&mut batch, r);
+            self.db.as_ref().unwrap().write(batch).unwrap();
+        }
+        copies
+    }
+
+    fn dedup(&mut self) -> usize {
+        let mut seen = HashSet::new();
+        let duplicates: Vec<_> = self
+            .cloned_records()
+            .into_iter()
+            .filter(|r| !seen.insert(r.clone()))
+            .collect();
+
+        // each removal must be written before the next one, as the duplicates are otherwise
+        // indistinguishable when looking up which row to remove.
+        for r in &duplicates {
+            let mut batch = WriteBatch::default();
+            self.remove(&mut batch, r);
+            self.db.as_ref().unwrap().write(batch).unwrap();
+        }
+        duplicates.len()
+    }
+
+    fn footprint(&self) -> u64 {
+        // RocksDB's estimate already covers the primary index and every secondary index
+        self.deep_size_of()
+    }
+
+    fn shrink_to_fit(&mut self) -> u64 {
+        // RocksDB reclaims space from removed records on its own as it compacts
+        0
+    }
+
+    fn stats(&self) -> LookupStats {
+        self.stats.get()
+    }
+
     fn clear(&mut self) {
         unreachable!("can't clear PersistentState")
     }
+
+    fn clear_index(&mut self, _: &[usize]) -> bool {
+        unreachable!("can't clear PersistentState")
+    }
+
+    fn into_parts(self: Box<Self>) -> (Vec<Vec<usize>>, Vec<Vec<DataType>>) {
+        (self.keys(), self.cloned_records())
+    }
+
+    fn into_keyed_iter(
+        self: Box<Self>,
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, Vec<Vec<DataType>>)>> {
+        let columns = match self.indices.first() {
+            Some(index) => index.columns.clone(),
+            None => return Box::new(iter::empty()),
+        };
+        let buckets: Vec<_> = self
+            .iter_buckets(&columns)
+            .map(|(key, rs)| (key, rs.into_iter().map(Cow::into_owned).collect()))
+            .collect();
+        Box::new(buckets.into_iter())
+    }
 }
 
 impl PersistentState {
@@ -279,6 +544,8 @@ impl PersistentState {
             seq: 0,
             indices,
             has_unique_index: primary_key.is_some(),
+            distinct: Vec::new(),
+            stats: Cell::new(LookupStats::default()),
             epoch: meta.epoch,
             db_opts: opts,
             db: Some(db),
@@ -425,6 +692,7 @@ impl PersistentState {
             KeyType::Quad(k) => serialize(k, extra),
             KeyType::Quin(k) => serialize(k, extra),
             KeyType::Sex(k) => serialize(k, extra),
+            KeyType::Multi(k) => serialize(k, extra),
         }
     }
 
@@ -452,6 +720,10 @@ impl PersistentState {
     // with exactly those values. I think the regular state implementation supports inserting
     // something like an Int and retrieving with a BigInt.
     fn insert(&mut self, batch: &mut WriteBatch, r: &[DataType]) {
+        for (col, hll) in &mut self.distinct {
+            hll.insert(&r[*col]);
+        }
+
         let serialized_pk = {
             let pk = Self::build_key(r, &self.indices[0].columns);
             if self.has_unique_index {
@@ -669,6 +941,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_state_lookup_len() {
+        let mut state = setup_persistent("persistent_state_lookup_len");
+        let first: Vec<DataType> = vec![10.into(), "Cat".into()];
+        let second: Vec<DataType> = vec![20.into(), "Cat".into()];
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        state.process_records(&mut vec![first, second].into(), None);
+
+        assert_eq!(
+            state.lookup_len(&[0], &KeyType::Single(&10.into())),
+            Some(1)
+        );
+        assert_eq!(state.lookup_len(&[0], &KeyType::Single(&5.into())), Some(0));
+        assert_eq!(
+            state.lookup_len(&[1], &KeyType::Single(&"Cat".into())),
+            Some(2)
+        );
+    }
+
     #[test]
     fn persistent_state_multiple_indices() {
         let mut state = setup_persistent("persistent_state_multiple_indices");
@@ -1064,6 +1356,52 @@ mod tests {
         assert_eq!(state.cloned_records(), vec![first, second]);
     }
 
+    #[test]
+    fn persistent_state_evictable_keys() {
+        let mut state = setup_persistent("persistent_state_evictable_keys");
+        state.add_key(&[0], None);
+        insert(&mut state, vec![10.into(), "Cat".into()]);
+        assert!(state.evictable_keys(Tag(0)).is_empty());
+    }
+
+    #[test]
+    fn persistent_state_stats() {
+        let mut state = setup_persistent("persistent_state_stats");
+        state.add_key(&[0], None);
+        insert(&mut state, vec![10.into(), "Cat".into()]);
+
+        state.lookup(&[0], &KeyType::Single(&10.into()));
+        state.lookup_len(&[0], &KeyType::Single(&20.into()));
+        assert!(state.contains_key(&[0], &KeyType::Single(&10.into())));
+        assert_eq!(
+            state.stats(),
+            LookupStats {
+                missing: 0,
+                empty: 1,
+                hits: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn persistent_state_iter_rows() {
+        let mut state = setup_persistent("persistent_state_iter_rows");
+        assert_eq!(state.iter_rows().count(), 0);
+
+        let first: Vec<DataType> = vec![10.into(), "Cat".into()];
+        let second: Vec<DataType> = vec![20.into(), "Cat".into()];
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        state.process_records(&mut vec![first.clone(), second.clone()].into(), None);
+
+        let mut rows: Vec<_> = state.iter_rows().map(Cow::into_owned).collect();
+        rows.sort();
+        assert_eq!(rows, vec![first, second]);
+
+        // rows are read from RocksDB rather than borrowed from an index
+        assert!(state.iter_index(&[0]).is_none());
+    }
+
     #[test]
     #[cfg(not(windows))]
     fn persistent_state_drop() {
@@ -1,15 +1,27 @@
-use super::mk_key::MakeKey;
+use super::mk_key::{MakeKey, RowKey};
 use crate::prelude::*;
 use crate::state::keyed_state::KeyedState;
 use common::SizeOf;
 use rand::prelude::*;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
+use std::time::Instant;
 
 pub(super) struct SingleState {
     key: Vec<usize>,
     state: KeyedState,
     partial: bool,
     rows: usize,
+    /// When each key was last filled or inserted into, if tracked.
+    inserted_at: Option<HashMap<Vec<DataType>, Instant>>,
+    /// The tracked column, along with the number of times each value of that column occurs in
+    /// each non-empty bucket. The values are ordered so that the extremes of a bucket are still
+    /// known after its current minimum or maximum is removed.
+    extremes: Option<(usize, HashMap<Vec<DataType>, BTreeMap<DataType, usize>>)>,
+    /// The tick at which each key was last looked up or filled, if tracked. Lookups only borrow
+    /// the state, so the ticks live behind a `RefCell`.
+    accessed_at: Option<RefCell<HashMap<Vec<DataType>, u64>>>,
 }
 
 macro_rules! insert_row_match_impl {
@@ -24,10 +36,8 @@ macro_rules! insert_row_match_impl {
 }
 
 macro_rules! remove_row_match_impl {
-    ($self:ident, $r:ident, $do_remove:ident, $map:ident, $($hint:tt)*) => {{
-        // TODO: can we avoid the Clone here?
-        let key = MakeKey::from_row(&$self.key, $r);
-        if let Some(ref mut rs) = $map.get_mut::<$($hint)*>(&key) {
+    ($self:ident, $r:ident, $do_remove:ident, $map:ident) => {{
+        if let Some(ref mut rs) = $map.get_mut(&RowKey::new(&$self.key, $r)) {
             return $do_remove(&mut $self.rows, rs);
         }
     }};
@@ -40,12 +50,154 @@ impl SingleState {
             state: columns.into(),
             partial,
             rows: 0,
+            inserted_at: None,
+            extremes: None,
+            accessed_at: None,
+        }
+    }
+
+    /// Start tracking the smallest and largest value of column `col` in each bucket.
+    ///
+    /// Only one column can be tracked per index. Tracking the same column again does nothing, but
+    /// panics if a different column is already tracked.
+    pub(super) fn track_extremes(&mut self, col: usize) {
+        if let Some((tracked, _)) = self.extremes {
+            assert_eq!(
+                tracked, col,
+                "extremes of column {} are already tracked for this index",
+                tracked
+            );
+            return;
+        }
+
+        let mut buckets: HashMap<_, BTreeMap<_, _>> = HashMap::new();
+        for (key, rs) in self.state.iter() {
+            for r in rs {
+                *buckets
+                    .entry(key.clone())
+                    .or_default()
+                    .entry(r[col].clone())
+                    .or_insert(0) += 1;
+            }
+        }
+        self.extremes = Some((col, buckets));
+    }
+
+    /// Returns the smallest and largest value of column `col` in the bucket for `key`, or `None`
+    /// if the bucket is empty or a hole. Returns `None` if `col` is not tracked.
+    pub(super) fn extremes(
+        &self,
+        col: usize,
+        key: &[DataType],
+    ) -> Option<Option<(&DataType, &DataType)>> {
+        match self.extremes {
+            Some((c, ref buckets)) if c == col => Some(buckets.get(key).map(|values| {
+                (
+                    values.keys().next().unwrap(),
+                    values.keys().next_back().unwrap(),
+                )
+            })),
+            _ => None,
+        }
+    }
+
+    /// Start tracking when each key was last looked up or filled.
+    pub(super) fn track_access_times(&mut self) {
+        if self.accessed_at.is_none() {
+            self.accessed_at = Some(RefCell::default());
+        }
+    }
+
+    /// Record that `key` was looked up or filled at `tick`, if access times are tracked.
+    pub(super) fn record_access(&self, key: Vec<DataType>, tick: u64) {
+        if let Some(ref times) = self.accessed_at {
+            times.borrow_mut().insert(key, tick);
+        }
+    }
+
+    /// Returns every key in this state along with the tick at which it was last looked up or
+    /// filled, or `None` if access times are not tracked.
+    pub(super) fn access_times(&self) -> Option<Vec<(u64, Vec<DataType>)>> {
+        let times = self.accessed_at.as_ref()?.borrow();
+        Some(
+            self.keys()
+                .into_iter()
+                .map(|k| (times.get(&k).cloned().unwrap_or(0), k))
+                .collect(),
+        )
+    }
+
+    /// Stop tracking anything about `key`, which is no longer in this state.
+    fn forget_key(&mut self, key: &[DataType]) {
+        if let Some((_, ref mut buckets)) = self.extremes {
+            buckets.remove(key);
+        }
+        if let Some(ref mut times) = self.accessed_at {
+            times.get_mut().remove(key);
+        }
+    }
+
+    /// Start tracking when each key was last filled or inserted into.
+    pub(super) fn track_insert_times(&mut self) {
+        if self.inserted_at.is_none() {
+            self.inserted_at = Some(HashMap::new());
+        }
+    }
+
+    /// Returns when the given key was last filled or inserted into, if tracked.
+    pub(super) fn inserted_at(&self, key: &[DataType]) -> Option<Instant> {
+        self.inserted_at.as_ref()?.get(key).cloned()
+    }
+
+    /// Stop tracking all keys last filled or inserted into before `cutoff`, and return them.
+    pub(super) fn take_inserted_before(&mut self, cutoff: Instant) -> Vec<Vec<DataType>> {
+        let times = match self.inserted_at {
+            Some(ref mut times) => times,
+            None => return Vec::new(),
+        };
+
+        let keys: Vec<_> = times
+            .iter()
+            .filter(|&(_, &t)| t < cutoff)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in &keys {
+            times.remove(k);
+        }
+        keys
+    }
+
+    fn touch(&mut self, key: Vec<DataType>) {
+        if let Some(ref mut times) = self.inserted_at {
+            times.insert(key, Instant::now());
         }
     }
 
     /// Inserts the given record, or returns false if a hole was encountered (and the record hence
     /// not inserted).
     pub(super) fn insert_row(&mut self, r: Row) -> bool {
+        if self.inserted_at.is_none() && self.extremes.is_none() {
+            return self.insert_row_untracked(r);
+        }
+
+        let key: Vec<_> = self.key.iter().map(|&c| r[c].clone()).collect();
+        let value = self.extremes.as_ref().map(|&(col, _)| r[col].clone());
+        let hit = self.insert_row_untracked(r);
+        if hit {
+            if let (Some(value), Some(&mut (_, ref mut buckets))) = (value, self.extremes.as_mut())
+            {
+                *buckets
+                    .entry(key.clone())
+                    .or_default()
+                    .entry(value)
+                    .or_insert(0) += 1;
+            }
+            self.touch(key);
+        }
+        hit
+    }
+
+    fn insert_row_untracked(&mut self, r: Row) -> bool {
         use indexmap::map::Entry;
         match self.state {
             KeyedState::Single(ref mut map) => {
@@ -68,6 +220,7 @@ impl SingleState {
             KeyedState::Quad(ref mut map) => insert_row_match_impl!(self, r, map),
             KeyedState::Quin(ref mut map) => insert_row_match_impl!(self, r, map),
             KeyedState::Sex(ref mut map) => insert_row_match_impl!(self, r, map),
+            KeyedState::Multi(ref mut map) => insert_row_match_impl!(self, r, map),
         }
 
         self.rows += 1;
@@ -76,6 +229,27 @@ impl SingleState {
 
     /// Attempt to remove row `r`.
     pub(super) fn remove_row(&mut self, r: &[DataType], hit: &mut bool) -> Option<Row> {
+        let removed = self.remove_row_untracked(r, hit);
+        if removed.is_some() {
+            if let Some((col, ref mut buckets)) = self.extremes {
+                let key: Vec<_> = self.key.iter().map(|&c| r[c].clone()).collect();
+                if let Some(values) = buckets.get_mut(&key) {
+                    if let Some(n) = values.get_mut(&r[col]) {
+                        *n -= 1;
+                        if *n == 0 {
+                            values.remove(&r[col]);
+                        }
+                    }
+                    if values.is_empty() {
+                        buckets.remove(&key);
+                    }
+                }
+            }
+        }
+        removed
+    }
+
+    fn remove_row_untracked(&mut self, r: &[DataType], hit: &mut bool) -> Option<Row> {
         let mut do_remove = |self_rows: &mut usize, rs: &mut Vec<Row>| -> Option<Row> {
             *hit = true;
             let rm = if rs.len() == 1 {
@@ -101,25 +275,35 @@ impl SingleState {
                 }
             }
             KeyedState::Double(ref mut map) => {
-                remove_row_match_impl!(self, r, do_remove, map, (DataType, _))
+                remove_row_match_impl!(self, r, do_remove, map)
             }
             KeyedState::Tri(ref mut map) => {
-                remove_row_match_impl!(self, r, do_remove, map, (DataType, _, _))
+                remove_row_match_impl!(self, r, do_remove, map)
             }
             KeyedState::Quad(ref mut map) => {
-                remove_row_match_impl!(self, r, do_remove, map, (DataType, _, _, _))
+                remove_row_match_impl!(self, r, do_remove, map)
             }
             KeyedState::Quin(ref mut map) => {
-                remove_row_match_impl!(self, r, do_remove, map, (DataType, _, _, _, _))
+                remove_row_match_impl!(self, r, do_remove, map)
             }
             KeyedState::Sex(ref mut map) => {
-                remove_row_match_impl!(self, r, do_remove, map, (DataType, _, _, _, _, _))
+                remove_row_match_impl!(self, r, do_remove, map)
+            }
+            KeyedState::Multi(ref mut map) => {
+                let key: Vec<_> = MakeKey::from_row(&self.key, r);
+                if let Some(ref mut rs) = map.get_mut(&key) {
+                    return do_remove(&mut self.rows, rs);
+                }
             }
         }
         None
     }
 
     pub(super) fn mark_filled(&mut self, key: Vec<DataType>) {
+        if self.inserted_at.is_some() {
+            self.touch(key.clone());
+        }
+
         let mut key = key.into_iter();
         let replaced = match self.state {
             KeyedState::Single(ref mut map) => map.insert(key.next().unwrap(), Vec::new()),
@@ -164,11 +348,13 @@ impl SingleState {
                 ),
                 Vec::new(),
             ),
+            KeyedState::Multi(ref mut map) => map.insert(key.collect(), Vec::new()),
         };
         assert!(replaced.is_none());
     }
 
     pub(super) fn mark_hole(&mut self, key: &[DataType]) -> u64 {
+        self.forget_key(key);
         let removed = match self.state {
             KeyedState::Single(ref mut m) => m.swap_remove(&(key[0])),
             KeyedState::Double(ref mut m) => {
@@ -186,6 +372,7 @@ impl SingleState {
             KeyedState::Sex(ref mut m) => {
                 m.swap_remove::<(DataType, _, _, _, _, _)>(&MakeKey::from_key(key))
             }
+            KeyedState::Multi(ref mut m) => m.swap_remove(key),
         };
         // mark_hole should only be called on keys we called mark_filled on
         removed
@@ -198,6 +385,15 @@ impl SingleState {
 
     pub(super) fn clear(&mut self) {
         self.rows = 0;
+        if let Some(ref mut times) = self.inserted_at {
+            times.clear();
+        }
+        if let Some((_, ref mut buckets)) = self.extremes {
+            buckets.clear();
+        }
+        if let Some(ref mut times) = self.accessed_at {
+            times.get_mut().clear();
+        }
         match self.state {
             KeyedState::Single(ref mut map) => map.clear(),
             KeyedState::Double(ref mut map) => map.clear(),
@@ -205,21 +401,37 @@ impl SingleState {
             KeyedState::Quad(ref mut map) => map.clear(),
             KeyedState::Quin(ref mut map) => map.clear(),
             KeyedState::Sex(ref mut map) => map.clear(),
+            KeyedState::Multi(ref mut map) => map.clear(),
         };
     }
 
     /// Evict `count` randomly selected keys from state and return them along with the number of
     /// bytes freed.
+    ///
+    /// If `protect_since` is given, keys that were filled or inserted into at or after that time
+    /// are not evicted. Finding the other keys requires a scan of the index.
     pub(super) fn evict_random_keys(
         &mut self,
         count: usize,
         rng: &mut ThreadRng,
+        protect_since: Option<Instant>,
     ) -> (u64, Vec<Vec<DataType>>) {
+        if let Some(cutoff) = protect_since {
+            let eligible: Vec<_> = self
+                .keys()
+                .into_iter()
+                .filter(|k| self.inserted_at(k).map(|t| t < cutoff).unwrap_or(true))
+                .collect();
+            let keys: Vec<_> = eligible.choose_multiple(rng, count).cloned().collect();
+            return (self.evict_keys(&keys), keys);
+        }
+
         let mut bytes_freed = 0;
         let mut keys = Vec::with_capacity(count);
         for _ in 0..count {
             if let Some((n, key)) = self.state.evict_with_seed(rng.gen()) {
                 bytes_freed += n;
+                self.forget_key(&key);
                 keys.push(key);
             } else {
                 break;
@@ -230,6 +442,9 @@ impl SingleState {
 
     /// Evicts a specified key from this state, returning the number of bytes freed.
     pub(super) fn evict_keys(&mut self, keys: &[Vec<DataType>]) -> u64 {
+        for k in keys {
+            self.forget_key(k);
+        }
         keys.iter().map(|k| self.state.evict(k)).sum()
     }
 
@@ -241,8 +456,57 @@ impl SingleState {
             KeyedState::Quad(ref map) => Box::new(map.values()),
             KeyedState::Quin(ref map) => Box::new(map.values()),
             KeyedState::Sex(ref map) => Box::new(map.values()),
+            KeyedState::Multi(ref map) => Box::new(map.values()),
         }
     }
+    pub(super) fn into_buckets(self) -> Box<dyn Iterator<Item = (Vec<DataType>, Vec<Row>)>> {
+        self.state.into_iter()
+    }
+    pub(super) fn into_rows(self) -> Box<dyn Iterator<Item = Row>> {
+        match self.state {
+            KeyedState::Single(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+            KeyedState::Double(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+            KeyedState::Tri(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+            KeyedState::Quad(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+            KeyedState::Quin(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+            KeyedState::Sex(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+            KeyedState::Multi(map) => Box::new(map.into_iter().flat_map(|(_, rs)| rs)),
+        }
+    }
+    pub(super) fn iter<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Vec<DataType>, &'a Vec<Row>)> + 'a> {
+        self.state.iter()
+    }
+    /// Returns the approximate number of bytes held by this index. Records already in `seen` are
+    /// not counted again, so that records shared with other indices are only counted once.
+    pub(super) fn footprint(&self, seen: &mut HashSet<*const Vec<DataType>>) -> u64 {
+        use std::mem::size_of;
+
+        self.state
+            .iter()
+            .map(|(key, rs)| {
+                // each entry also stores the key's hash
+                let entry = key.iter().map(SizeOf::deep_size_of).sum::<u64>()
+                    + size_of::<u64>() as u64
+                    + size_of::<Vec<Row>>() as u64
+                    + (rs.capacity() * size_of::<Row>()) as u64;
+                let records: u64 = rs
+                    .iter()
+                    .filter(|r| seen.insert(&*r.0))
+                    // the reference counts live next to the record
+                    .map(|r| r.deep_size_of() + 2 * size_of::<usize>() as u64)
+                    .sum();
+                entry + records
+            })
+            .sum()
+    }
+    pub(super) fn buckets<'a>(&'a self) -> Box<dyn Iterator<Item = &'a [Row]> + 'a> {
+        Box::new(self.state.values().map(|rs| &rs[..]))
+    }
+    pub(super) fn keys(&self) -> Vec<Vec<DataType>> {
+        self.state.keys()
+    }
     pub(super) fn key(&self) -> &[usize] {
         &self.key
     }
@@ -252,6 +516,15 @@ impl SingleState {
     pub(super) fn rows(&self) -> usize {
         self.rows
     }
+    pub(super) fn shrink_to_fit(&mut self) -> u64 {
+        self.state.shrink_to_fit()
+    }
+    pub(super) fn reserve(&mut self, additional: usize) {
+        self.state.reserve(additional)
+    }
+    pub(super) fn contains_key(&self, key: &KeyType) -> bool {
+        self.state.lookup(key).is_some()
+    }
     pub(super) fn lookup<'a>(&'a self, key: &KeyType) -> LookupResult<'a> {
         if let Some(rs) = self.state.lookup(key) {
             LookupResult::Some(RecordResult::Borrowed(&rs[..]))
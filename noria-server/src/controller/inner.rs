@@ -224,6 +224,9 @@ impl ControllerInner {
             (Method::GET, "/flush_partial") => {
                 Ok(Ok(json::to_string(&self.flush_partial()).unwrap()))
             }
+            (Method::POST, "/schema_version") => {
+                Ok(Ok(json::to_string(&self.recipe.version()).unwrap()))
+            }
             (Method::POST, "/inputs") => Ok(Ok(json::to_string(&self.inputs()).unwrap())),
             (Method::POST, "/outputs") => Ok(Ok(json::to_string(&self.outputs()).unwrap())),
             (Method::GET, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
@@ -871,6 +874,7 @@ impl ControllerInner {
             table_name: node.name().to_owned(),
             columns,
             schema,
+            schema_version: self.recipe.version(),
         })
     }
 
@@ -509,14 +509,6 @@ fn make_grouped_node(
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
 ) -> FlowNode {
     assert!(!group_by.is_empty());
-    assert!(
-        group_by.len() <= 6,
-        format!(
-            "can't have >6 group columns due to compound key restrictions, {} needs {}",
-            name,
-            group_by.len()
-        )
-    );
 
     let parent_na = parent.borrow().flow_node_addr().unwrap();
     let column_names = column_names(columns);
@@ -233,6 +233,214 @@ async fn base_mutation() {
     );
 }
 
+#[tokio::test(threadpool)]
+async fn base_counters() {
+    let mut g = start_simple("base_counters").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    write.insert(vec![1.into(), 4.into()]).await.unwrap();
+    write.increment(vec![1.into()], 1, 3).await.unwrap();
+    write.decrement(vec![1.into()], 1, 1).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        read.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 6.into()]]
+    );
+
+    // counters can only change columns that exist
+    match write.increment(vec![1.into()], 2, 1).await {
+        Err(noria::error::TableError::WrongColumnCount(2, 3)) => {}
+        r => panic!("expected a wrong column count, got {:?}", r),
+    }
+}
+
+#[tokio::test(threadpool)]
+async fn empty_perform_all() {
+    let mut g = start_simple("empty_perform_all").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    // an empty batch should complete without sending anything
+    write
+        .perform_all(Vec::<noria::TableOperation>::new())
+        .await
+        .unwrap();
+
+    // and the table should still be usable afterwards
+    write.insert(vec![1.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        read.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+}
+
+#[tokio::test(threadpool)]
+async fn perform_all_valid() {
+    let mut g = start_simple("perform_all_valid").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    let rows: Vec<Vec<DataType>> = vec![
+        vec![1.into(), 2.into()],
+        vec![2.into()],
+        vec![3.into(), 4.into()],
+    ];
+    let skipped = write.perform_all_valid(rows).await.unwrap();
+    match &skipped[..] {
+        [(1, noria::error::TableError::WrongColumnCount(2, 1))] => {}
+        r => panic!("expected the second row to be skipped, got {:?}", r),
+    }
+
+    sleep().await;
+    assert_eq!(read.lookup(&[1.into()], true).await.unwrap().len(), 1);
+    assert!(read.lookup(&[2.into()], true).await.unwrap().is_empty());
+    assert_eq!(read.lookup(&[3.into()], true).await.unwrap().len(), 1);
+}
+
+#[tokio::test(threadpool)]
+async fn max_pending_writes() {
+    let mut g = start_simple("max_pending_writes").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[1]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    // with only one write allowed in flight, each batch has to wait for the one before it
+    write.set_max_pending_writes(Some(1));
+    let mut progress = Vec::new();
+    write
+        .perform_all_with_progress((0..10).map(|i| vec![i.into(), 0.into()]), 2, |n| {
+            progress.push(n)
+        })
+        .await
+        .unwrap();
+    assert_eq!(progress, vec![2, 4, 6, 8, 10]);
+    assert_eq!(write.pending_writes(), 0);
+
+    sleep().await;
+    assert_eq!(read.lookup(&[0.into()], true).await.unwrap().len(), 10);
+}
+
+#[tokio::test(threadpool)]
+async fn delete_chunked() {
+    let mut g = start_simple("delete_chunked").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[1]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    write
+        .perform_all((0..10).map(|i| vec![i.into(), (i % 2).into()]))
+        .await
+        .unwrap();
+
+    // delete the even rows, two at a time
+    let mut progress = Vec::new();
+    write
+        .delete_chunked((0..10).step_by(2).map(|i| vec![i.into()]), 2, |n| {
+            progress.push(n)
+        })
+        .await
+        .unwrap();
+    assert_eq!(progress, vec![2, 4, 5]);
+
+    sleep().await;
+    assert!(read.lookup(&[0.into()], true).await.unwrap().is_empty());
+    assert_eq!(read.lookup(&[1.into()], true).await.unwrap().len(), 5);
+}
+
+#[tokio::test(threadpool)]
+async fn delete_many() {
+    let mut g = start_simple("delete_many").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[1]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    write
+        .perform_all((0..10).map(|i| vec![i.into(), (i % 2).into()]))
+        .await
+        .unwrap();
+
+    // a malformed key should fail the whole batch
+    match write
+        .delete_many(vec![vec![0.into()], vec![2.into(), 0.into()]])
+        .await
+    {
+        Err(noria::error::TableError::WrongKeyColumnCountAt(1, 1, 2)) => {}
+        r => panic!("expected batch to be rejected, got {:?}", r),
+    }
+
+    write
+        .delete_many((0..10).step_by(2).map(|i| vec![i.into()]))
+        .await
+        .unwrap();
+
+    sleep().await;
+    assert!(read.lookup(&[0.into()], true).await.unwrap().is_empty());
+    assert_eq!(read.lookup(&[1.into()], true).await.unwrap().len(), 5);
+}
+
+#[tokio::test(threadpool)]
+async fn pause_writes() {
+    let mut g = start_simple("pause_writes").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+
+    // pausing applies to clones of the table as well
+    write.clone().pause();
+    match write.insert(vec![1.into(), 2.into()]).await {
+        Err(noria::error::TableError::Paused) => {}
+        r => panic!("expected write to be rejected, got {:?}", r),
+    }
+
+    write.resume();
+    write.insert(vec![1.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        read.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+}
+
 #[tokio::test(threadpool)]
 async fn shared_interdomain_ancestor() {
     // set up graph
@@ -1444,6 +1652,62 @@ async fn migrate_drop_columns() {
     assert!(res.contains(&vec![id.clone(), "b".into(), "c".into()]));
 }
 
+#[tokio::test(threadpool)]
+async fn update_with_dropped_columns() {
+    use noria::{Modification, Operation};
+
+    let mut g = start_simple("update_with_dropped_columns").await;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base(
+                "a",
+                &["a", "b", "c", "d"],
+                Base::new(vec![0.into(), "b".into(), 0.into(), "d".into()]).with_key(vec![0]),
+            );
+            mig.maintain_anonymous(a, &[0]);
+            a
+        })
+        .await;
+    g.migrate(move |mig| {
+        mig.drop_column(a, 1);
+        mig.drop_column(a, 3);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+    write.insert(vec![1.into(), 2.into()]).await.unwrap();
+    write
+        .update(
+            vec![1.into()],
+            vec![(1, Modification::Apply(Operation::Add, 3.into()))],
+        )
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        read.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "b".into(), 5.into(), "d".into()]]
+    );
+
+    write
+        .insert_or_update(
+            vec![1.into(), 10.into()],
+            vec![(1, Modification::FromInserted(1))],
+        )
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        read.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "b".into(), 10.into(), "d".into()]]
+    );
+
+    write.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert!(read.lookup(&[1.into()], true).await.unwrap().is_empty());
+}
+
 #[tokio::test(threadpool)]
 async fn key_on_added() {
     // set up graph
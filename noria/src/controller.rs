@@ -404,6 +404,16 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("install_recipe", new_recipe, "failed to install recipe")
     }
 
+    /// Fetch the version of the currently installed recipe.
+    ///
+    /// The version is bumped on every migration, and can be compared against
+    /// [`Table::schema_version`] to detect table handles that predate a migration.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn schema_version(&mut self) -> impl Future<Output = Result<usize, failure::Error>> {
+        self.rpc("schema_version", (), "failed to fetch schema version")
+    }
+
     /// Fetch a graphviz description of the dataflow graph.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -497,6 +497,76 @@ impl<'a> Into<f64> for &'a DataType {
     }
 }
 
+/// Typed access to the columns of a row, such as one returned from a lookup.
+///
+/// Each accessor returns `None` if the column does not exist or holds a value of another type.
+/// Integer values are converted between widths and signedness where the value fits.
+pub trait TypedColumns {
+    /// Get column `col` as an `i64`.
+    fn get_i64(&self, col: usize) -> Option<i64>;
+    /// Get column `col` as a `u64`.
+    fn get_u64(&self, col: usize) -> Option<u64>;
+    /// Get column `col` as an `f64`. Integer values are converted as well.
+    fn get_f64(&self, col: usize) -> Option<f64>;
+    /// Get column `col` as a string slice. Returns `None` if the string is not valid UTF-8.
+    fn get_str(&self, col: usize) -> Option<&str>;
+    /// Get column `col` as a timestamp.
+    fn get_timestamp(&self, col: usize) -> Option<NaiveDateTime>;
+}
+
+impl TypedColumns for [DataType] {
+    fn get_i64(&self, col: usize) -> Option<i64> {
+        use std::convert::TryFrom;
+        match *self.get(col)? {
+            DataType::Int(i) => Some(i64::from(i)),
+            DataType::UnsignedInt(i) => Some(i64::from(i)),
+            DataType::BigInt(i) => Some(i),
+            DataType::UnsignedBigInt(i) => i64::try_from(i).ok(),
+            _ => None,
+        }
+    }
+
+    fn get_u64(&self, col: usize) -> Option<u64> {
+        use std::convert::TryFrom;
+        match *self.get(col)? {
+            DataType::Int(i) => u64::try_from(i).ok(),
+            DataType::UnsignedInt(i) => Some(u64::from(i)),
+            DataType::BigInt(i) => u64::try_from(i).ok(),
+            DataType::UnsignedBigInt(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    fn get_f64(&self, col: usize) -> Option<f64> {
+        match *self.get(col)? {
+            DataType::Real(i, f) => Some(i as f64 + f64::from(f) / FLOAT_PRECISION),
+            DataType::Int(i) => Some(f64::from(i)),
+            DataType::UnsignedInt(i) => Some(f64::from(i)),
+            DataType::BigInt(i) => Some(i as f64),
+            DataType::UnsignedBigInt(i) => Some(i as f64),
+            _ => None,
+        }
+    }
+
+    fn get_str(&self, col: usize) -> Option<&str> {
+        match *self.get(col)? {
+            DataType::Text(ref s) => s.to_str().ok(),
+            DataType::TinyText(ref bts) => {
+                let len = bts.iter().position(|&i| i == 0).unwrap_or(TINYTEXT_WIDTH);
+                std::str::from_utf8(&bts[..len]).ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn get_timestamp(&self, col: usize) -> Option<NaiveDateTime> {
+        match *self.get(col)? {
+            DataType::Timestamp(ts) => Some(ts),
+            _ => None,
+        }
+    }
+}
+
 impl From<String> for DataType {
     fn from(s: String) -> Self {
         let len = s.as_bytes().len();
@@ -613,10 +683,37 @@ pub enum Modification {
     Set(DataType),
     /// Use the given [`Operation`] to combine the existing value and this one.
     Apply(Operation, DataType),
+    /// Set the cell to the value of the given column in the row that was being inserted.
+    ///
+    /// Only valid in the `update` of a [`TableOperation::InsertOrUpdate`].
+    FromInserted(usize),
+    /// Use the given [`Operation`] to combine the existing value and the value of the given
+    /// column in the row that was being inserted.
+    ///
+    /// Only valid in the `update` of a [`TableOperation::InsertOrUpdate`].
+    ApplyInserted(Operation, usize),
     /// Leave the existing value as-is.
     None,
 }
 
+impl Modification {
+    /// Set the cell to `value`, converting it into a [`DataType`].
+    ///
+    /// ```
+    /// # use noria::{DataType, Modification};
+    /// assert_eq!(Modification::set(42), Modification::Set(DataType::Int(42)));
+    /// ```
+    pub fn set<V: Into<DataType>>(value: V) -> Self {
+        Modification::Set(value.into())
+    }
+
+    /// Combine the existing value and `value` using `op`, converting `value` into a
+    /// [`DataType`].
+    pub fn apply<V: Into<DataType>>(op: Operation, value: V) -> Self {
+        Modification::Apply(op, value.into())
+    }
+}
+
 /// An operation to apply to a base table.
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum TableOperation {
@@ -635,6 +732,30 @@ pub enum TableOperation {
         /// These modifications will be applied to the columns of an existing row.
         update: Vec<Modification>,
     },
+    /// Delete a row with the contained key, but only if the row's current value in each of the
+    /// columns in `expect` is equal to the given value.
+    ConditionalDelete {
+        /// The key.
+        key: Vec<DataType>,
+        /// The expected values of the given columns of the row.
+        expect: Vec<(usize, DataType)>,
+    },
+    /// Insert the contained row, unless the base has recently applied an insert with the same
+    /// `token`.
+    ///
+    /// This makes it safe to retry an insert whose acknowledgement was lost.
+    IdempotentInsert {
+        /// The row to insert.
+        row: Vec<DataType>,
+        /// A client-chosen token that identifies this insert across retries.
+        token: u64,
+    },
+    /// Insert the contained row, unless a row with the same key already exists. Unlike
+    /// `InsertOrUpdate`, the existing row is then left as is.
+    InsertIfAbsent {
+        /// The row to insert.
+        row: Vec<DataType>,
+    },
     /// Update an existing row with the given `key`.
     Update {
         /// The modifications to make to each column of the existing row.
@@ -650,6 +771,8 @@ impl TableOperation {
         match *self {
             TableOperation::Insert(ref r) => Some(r),
             TableOperation::InsertOrUpdate { ref row, .. } => Some(row),
+            TableOperation::IdempotentInsert { ref row, .. } => Some(row),
+            TableOperation::InsertIfAbsent { ref row } => Some(row),
             _ => None,
         }
     }
@@ -687,6 +810,33 @@ mod tests {
         assert_eq!(original, converted);
     }
 
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn typed_columns() {
+        let long = "a string that does not fit inline";
+        let row: Vec<DataType> = vec![
+            3.into(),
+            DataType::UnsignedBigInt(u64::max_value()),
+            (2.5).into(),
+            "short".into(),
+            long.into(),
+            DataType::None,
+        ];
+
+        assert_eq!(row.get_i64(0), Some(3));
+        assert_eq!(row.get_u64(0), Some(3));
+        assert_eq!(row.get_f64(0), Some(3.0));
+        assert_eq!(row.get_i64(1), None);
+        assert_eq!(row.get_u64(1), Some(u64::max_value()));
+        assert_eq!(row.get_f64(2), Some(2.5));
+        assert_eq!(row.get_i64(2), None);
+        assert_eq!(row.get_str(3), Some("short"));
+        assert_eq!(row.get_str(4), Some(long));
+        assert_eq!(row.get_str(0), None);
+        assert_eq!(row.get_timestamp(5), None);
+        assert_eq!(row.get_i64(6), None);
+    }
+
     #[test]
     fn add_data_types() {
         assert_eq!(&DataType::from(1) + &DataType::from(2), 3.into());
@@ -152,7 +152,7 @@ pub mod prelude {
 
 /// Noria errors.
 pub mod error {
-    pub use crate::table::TableError;
+    pub use crate::table::{ConnectError, TableError};
     pub use crate::view::ViewError;
 }
 
@@ -209,8 +209,8 @@ impl<T> From<T> for Tagged<T> {
 }
 
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
-pub use crate::data::{DataType, Modification, Operation, TableOperation};
-pub use crate::table::{SyncTable, Table};
+pub use crate::data::{DataType, Modification, Operation, TableOperation, TypedColumns};
+pub use crate::table::{MetricsSink, SyncTable, Table};
 pub use crate::view::{SyncView, View};
 
 #[doc(hidden)]
@@ -5,16 +5,20 @@ use crate::internal::*;
 use crate::LocalOrNot;
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use futures_util::{
     future, ready, stream::futures_unordered::FuturesUnordered, try_future::TryFutureExt,
     try_stream::TryStreamExt,
 };
-use nom_sql::CreateTableStatement;
+use nom_sql::{ColumnConstraint, CreateTableStatement, SqlType};
 use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 use std::{fmt, io};
 use tokio::prelude::*;
 use tokio_tower::multiplex;
@@ -48,18 +52,52 @@ impl Service<()> for TableEndpoint {
     }
 
     fn call(&mut self, _: ()) -> Self::Future {
-        let f = tokio::net::TcpStream::connect(self.0);
+        let addr = self.0;
+        let f = tokio::net::TcpStream::connect(addr);
         async move {
-            let mut s = f.await?;
-            s.set_nodelay(true)?;
-            s.write_all(&[CONNECTION_FROM_BASE]).await.unwrap();
-            s.flush().await.unwrap();
+            let connect = async move {
+                let mut s = f.await?;
+                s.set_nodelay(true)?;
+                s.write_all(&[CONNECTION_FROM_BASE]).await?;
+                s.flush().await?;
+                Ok::<_, io::Error>(s)
+            };
+            let s = connect
+                .await
+                .map_err(|e| io::Error::new(e.kind(), ConnectError { addr, source: e }))?;
             let s = AsyncBincodeStream::from(s).for_async();
             Ok(multiplex::MultiplexTransport::new(s, Tagger::default()))
         }
     }
 }
 
+/// A failure to connect to one of the shards of a base table.
+///
+/// This is returned wrapped in an [`io::Error`] of the same kind as `source`.
+#[derive(Debug)]
+pub struct ConnectError {
+    /// The address of the shard that could not be reached.
+    pub addr: SocketAddr,
+    /// The underlying connection error.
+    pub source: io::Error,
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to connect to base table shard at {}: {}",
+            self.addr, self.source
+        )
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
 pub(crate) type TableRpc = Buffer<
     Pool<
         multiplex::client::Maker<TableEndpoint, Tagged<LocalOrNot<Input>>>,
@@ -69,6 +107,19 @@ pub(crate) type TableRpc = Buffer<
     Tagged<LocalOrNot<Input>>,
 >;
 
+fn table_rpc(addr: SocketAddr) -> TableRpc {
+    // TODO: maybe always use the same local port?
+    Buffer::new(
+        pool::Builder::new()
+            .urgency(0.01)
+            .loaded_above(0.2)
+            .underutilized_below(0.000000001)
+            .max_services(Some(32))
+            .build(multiplex::client::Maker::new(TableEndpoint(addr)), ()),
+        50,
+    )
+}
+
 /// A failed [`SyncTable`] operation.
 #[derive(Debug, Fail)]
 pub enum TableError {
@@ -86,6 +137,55 @@ pub enum TableError {
     )]
     WrongKeyColumnCount(usize, usize),
 
+    /// The key at the given position in a batch had the wrong number of columns.
+    #[fail(
+        display = "wrong number of key columns used for key {}: expected {}, got {}",
+        _0, _1, _2
+    )]
+    WrongKeyColumnCountAt(usize, usize, usize),
+
+    /// A column that does not exist in the table was named.
+    #[fail(display = "unknown column: {}", _0)]
+    UnknownColumn(String),
+
+    /// A column that has no default value was not given a value.
+    #[fail(display = "no value given for column without a default: {}", _0)]
+    MissingColumn(String),
+
+    /// A value does not match the declared SQL type of its column.
+    #[fail(
+        display = "column {} has type {}, but was given {:?}",
+        column, expected, got
+    )]
+    TypeMismatch {
+        /// The column the value was given for.
+        column: String,
+        /// The declared type of the column.
+        expected: SqlType,
+        /// The value that was given.
+        got: DataType,
+    },
+
+    /// The columns of a columnar batch do not all hold the same number of values.
+    #[fail(
+        display = "columns in batch have different lengths: expected {}, got {}",
+        _0, _1
+    )]
+    ColumnLengthMismatch(usize, usize),
+
+    /// A write was not acknowledged within the timeout set with [`Table::set_timeout`].
+    #[fail(display = "write was not acknowledged in time")]
+    Timeout,
+
+    /// Writes to the table have been paused with [`Table::pause`].
+    #[fail(display = "writes to this table are paused")]
+    Paused,
+
+    /// A modification of the given column reads from the inserted row, but is not part of an
+    /// insert-or-update.
+    #[fail(display = "modification of column {} refers to an inserted row", _0)]
+    NoInsertedRow(usize),
+
     /// The underlying connection to Noria produced an error.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -98,11 +198,81 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
 }
 
 #[doc(hidden)]
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone)]
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
     pub tracer: Tracer,
+    /// If set, `data` is compressed on the wire if it serializes to more than this many bytes.
+    pub compress_above: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum WireData<D> {
+    Plain(D),
+    Deflated(Vec<u8>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireInput<D, T> {
+    dst: LocalNodeIndex,
+    data: WireData<D>,
+    tracer: T,
+}
+
+impl Serialize for Input {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::Error;
+
+        let compress = match self.compress_above {
+            Some(threshold) => {
+                bincode::serialized_size(&self.data).map_err(S::Error::custom)? > threshold as u64
+            }
+            None => false,
+        };
+
+        let data = if compress {
+            let mut e = DeflateEncoder::new(Vec::new(), Compression::fast());
+            bincode::serialize_into(&mut e, &self.data).map_err(S::Error::custom)?;
+            WireData::Deflated(e.finish().map_err(S::Error::custom)?)
+        } else {
+            WireData::Plain(&self.data)
+        };
+
+        WireInput {
+            dst: self.dst,
+            data,
+            tracer: &self.tracer,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Input {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let WireInput { dst, data, tracer } =
+            WireInput::<Vec<TableOperation>, Tracer>::deserialize(deserializer)?;
+        let data = match data {
+            WireData::Plain(data) => data,
+            WireData::Deflated(bytes) => bincode::deserialize_from(DeflateDecoder::new(&bytes[..]))
+                .map_err(D::Error::custom)?,
+        };
+
+        Ok(Input {
+            dst,
+            data,
+            tracer,
+            compress_above: None,
+        })
+    }
 }
 
 impl fmt::Debug for Input {
@@ -128,6 +298,7 @@ pub struct TableBuilder {
     pub table_name: String,
     pub columns: Vec<String>,
     pub schema: Option<CreateTableStatement>,
+    pub schema_version: usize,
 }
 
 impl TableBuilder {
@@ -148,16 +319,7 @@ impl TableBuilder {
             let s = match rpcs.entry((addr, shardi)) {
                 Entry::Occupied(e) => e.get().clone(),
                 Entry::Vacant(h) => {
-                    // TODO: maybe always use the same local port?
-                    let c = Buffer::new(
-                        pool::Builder::new()
-                            .urgency(0.01)
-                            .loaded_above(0.2)
-                            .underutilized_below(0.000000001)
-                            .max_services(Some(32))
-                            .build(multiplex::client::Maker::new(TableEndpoint(addr)), ()),
-                        50,
-                    );
+                    let c = table_rpc(addr);
                     h.insert(c.clone());
                     c
                 }
@@ -174,18 +336,253 @@ impl TableBuilder {
             columns: self.columns,
             dropped: self.dropped,
             tracer: None,
+            trace_all: None,
+            compress_above: None,
+            timeout: None,
+            retries: 0,
+            retry_backoff: Duration::from_millis(0),
             table_name: self.table_name,
             schema: self.schema,
+            schema_version: self.schema_version,
             dst_is_local: false,
 
             shard_addrs: addrs,
             shards: conns,
+            inflight: Arc::new(Inflight::default()),
+            max_pending: None,
+            pause: Arc::new(Pause::default()),
+            block_when_paused: false,
+            metrics: Arc::new(NoopMetricsSink),
 
             dispatch,
         })
     }
 }
 
+/// Insert the default value of each dropped column into `row` at that column's index.
+fn inject_defaults(row: &mut Vec<DataType>, dropped: &VecMap<DataType>) {
+    inject_columns(row, dropped, DataType::clone)
+}
+
+/// Check that every modification in `set` that reads from the inserted row reads a column that
+/// exists, and that there is an inserted row to read from at all.
+fn check_modifications(
+    set: &[Modification],
+    ncols: usize,
+    inserted: bool,
+) -> Result<(), TableError> {
+    for (coli, m) in set.iter().enumerate() {
+        match *m {
+            Modification::FromInserted(from) | Modification::ApplyInserted(_, from) => {
+                if !inserted {
+                    return Err(TableError::NoInsertedRow(coli));
+                }
+                if from >= ncols {
+                    return Err(TableError::WrongColumnCount(ncols, from + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check that every column a conditional delete compares against exists.
+fn check_expect(expect: &[(usize, DataType)], ncols: usize) -> Result<(), TableError> {
+    match expect.iter().find(|&&(coli, _)| coli >= ncols) {
+        Some(&(coli, _)) => Err(TableError::WrongColumnCount(ncols, coli + 1)),
+        None => Ok(()),
+    }
+}
+
+/// Insert `Modification::None` at the index of each dropped column in `set`, and point any
+/// modification that reads from the inserted row at that column's index in the base.
+fn inject_modifications(set: &mut Vec<Modification>, dropped: &VecMap<DataType>) {
+    inject_columns(set, dropped, |_| Modification::None);
+    for m in set {
+        match *m {
+            Modification::FromInserted(ref mut col)
+            | Modification::ApplyInserted(_, ref mut col) => *col = base_column(*col, dropped),
+            _ => {}
+        }
+    }
+}
+
+/// Insert the value `default` produces from each dropped column's default into `row` at that
+/// column's index.
+///
+/// Every element of `row` is moved exactly once, into a new vector with room for the defaults.
+fn inject_columns<T>(
+    row: &mut Vec<T>,
+    dropped: &VecMap<DataType>,
+    default: impl Fn(&DataType) -> T,
+) {
+    let mut existing =
+        std::mem::replace(row, Vec::with_capacity(row.len() + dropped.len())).into_iter();
+    for (i, d) in dropped {
+        row.extend(existing.by_ref().take(i.saturating_sub(row.len())));
+        row.push(default(d));
+    }
+    row.extend(existing);
+}
+
+/// Map the index of a column as seen through a `Table` to its index in the base, which still
+/// includes the dropped columns.
+fn base_column(mut col: usize, dropped: &VecMap<DataType>) -> usize {
+    for i in dropped.keys() {
+        if i > col {
+            break;
+        }
+        col += 1;
+    }
+    col
+}
+
+/// Arrange the values in `row`, keyed by column name, in the order of `columns`.
+///
+/// Columns missing from `row` take their default value from `schema`, which is NULL if the
+/// schema does not give one. Without a schema, every column must be given.
+fn named_row(
+    columns: &[String],
+    schema: Option<&CreateTableStatement>,
+    mut row: HashMap<String, DataType>,
+) -> Result<Vec<DataType>, TableError> {
+    if let Some(name) = row.keys().find(|&name| !columns.contains(name)) {
+        return Err(TableError::UnknownColumn(name.clone()));
+    }
+
+    let default = |name: &str| {
+        let field = schema?.fields.iter().find(|f| f.column.name == name)?;
+        let mut default = Some(DataType::None);
+        for c in &field.constraints {
+            match *c {
+                ColumnConstraint::DefaultValue(ref dv) => return Some(dv.into()),
+                ColumnConstraint::NotNull => default = None,
+                _ => {}
+            }
+        }
+        default
+    };
+
+    columns
+        .iter()
+        .map(|name| match row.remove(name) {
+            Some(value) => Ok(value),
+            None => default(name).ok_or_else(|| TableError::MissingColumn(name.clone())),
+        })
+        .collect()
+}
+
+/// Check that each value in `row` matches the SQL type declared for its column in `schema`.
+///
+/// `NULL` is accepted for columns that are not declared `NOT NULL` or part of the primary key.
+/// Columns that are not in `schema`, and types that values are not checked against, accept any
+/// value.
+fn check_types(
+    columns: &[String],
+    schema: &CreateTableStatement,
+    row: &[DataType],
+) -> Result<(), TableError> {
+    for (name, value) in columns.iter().zip(row) {
+        let field = match schema.fields.iter().find(|f| f.column.name == *name) {
+            Some(field) => field,
+            None => continue,
+        };
+
+        let ok = if value.is_none() {
+            !field.constraints.iter().any(|c| match *c {
+                ColumnConstraint::NotNull | ColumnConstraint::PrimaryKey => true,
+                _ => false,
+            })
+        } else {
+            match field.sql_type {
+                SqlType::Bool
+                | SqlType::Tinyint(_)
+                | SqlType::Int(_)
+                | SqlType::UnsignedInt(_)
+                | SqlType::Bigint(_)
+                | SqlType::UnsignedBigint(_) => value.is_integer(),
+                SqlType::Real | SqlType::Float | SqlType::Double => value.is_real(),
+                SqlType::Char(_)
+                | SqlType::Varchar(_)
+                | SqlType::Tinytext
+                | SqlType::Mediumtext
+                | SqlType::Longtext
+                | SqlType::Text => value.is_string(),
+                SqlType::Date | SqlType::DateTime(_) | SqlType::Timestamp => value.is_datetime(),
+                _ => true,
+            }
+        };
+
+        if !ok {
+            return Err(TableError::TypeMismatch {
+                column: name.clone(),
+                expected: field.sql_type.clone(),
+                got: value.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The number of requests issued through a `Table` and its clones that have not yet been
+/// acknowledged, along with the writes that are blocked until that number drops.
+#[derive(Default)]
+struct Inflight {
+    count: AtomicUsize,
+    blocked: Mutex<Vec<Waker>>,
+}
+
+/// Keeps a request counted as in-flight until it completes or is dropped.
+struct InflightGuard(Arc<Inflight>);
+
+impl InflightGuard {
+    fn new(inflight: &Arc<Inflight>) -> Self {
+        inflight.count.fetch_add(1, atomic::Ordering::SeqCst);
+        InflightGuard(inflight.clone())
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, atomic::Ordering::SeqCst);
+        for w in self.0.blocked.lock().unwrap().drain(..) {
+            w.wake();
+        }
+    }
+}
+
+/// Whether writes through a `Table` and its clones are currently paused, along with the writes
+/// that are blocked until they are resumed.
+#[derive(Default)]
+struct Pause {
+    paused: AtomicBool,
+    blocked: Mutex<Vec<Waker>>,
+}
+
+/// Receives events about the writes issued through a [`Table`], for example to export them as
+/// metrics.
+///
+/// Every method does nothing by default, so implementors only need to handle the events they
+/// care about. Methods are called inline with the writes, and so should not block.
+pub trait MetricsSink: Send + Sync {
+    /// Called when a request carrying `ops` operations is sent.
+    fn write_issued(&self, _ops: usize) {}
+
+    /// Called when a request is acknowledged, `latency` after it was sent.
+    fn write_acked(&self, _latency: Duration) {}
+
+    /// Called when a write fails, either before or after it was sent.
+    ///
+    /// A write that is retried, as configured with [`Table::set_retries`], is only reported once,
+    /// and only if it eventually fails. Readiness errors returned from `poll_ready` when driving
+    /// the `Service` implementation of [`Table`] directly are not reported.
+    fn write_failed(&self, _error: &TableError) {}
+}
+
+struct NoopMetricsSink;
+impl MetricsSink for NoopMetricsSink {}
+
 /// A `Table` is used to perform writes, deletes, and other operations to data in base tables.
 ///
 /// If you create multiple `Table` handles from a single `ControllerHandle`, they may share
@@ -201,12 +598,23 @@ pub struct Table {
     columns: Vec<String>,
     dropped: VecMap<DataType>,
     tracer: Tracer,
+    trace_all: Option<u64>,
+    compress_above: Option<usize>,
+    timeout: Option<Duration>,
+    retries: usize,
+    retry_backoff: Duration,
     table_name: String,
     schema: Option<CreateTableStatement>,
+    schema_version: usize,
     dst_is_local: bool,
 
     shards: Vec<TableRpc>,
     shard_addrs: Vec<SocketAddr>,
+    inflight: Arc<Inflight>,
+    max_pending: Option<usize>,
+    pause: Arc<Pause>,
+    block_when_paused: bool,
+    metrics: Arc<dyn MetricsSink>,
 
     dispatch: tracing::Dispatch,
 }
@@ -222,6 +630,11 @@ impl fmt::Debug for Table {
             .field("dropped", &self.dropped)
             .field("table_name", &self.table_name)
             .field("schema", &self.schema)
+            .field("schema_version", &self.schema_version)
+            .field("compress_above", &self.compress_above)
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("max_pending", &self.max_pending)
             .field("dst_is_local", &self.dst_is_local)
             .field("shard_addrs", &self.shard_addrs)
             .finish()
@@ -235,8 +648,33 @@ impl Service<Input> for Table {
     type Future = impl Future<Output = Result<Tagged<()>, TableError>> + Send;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.pause.paused.load(atomic::Ordering::SeqCst) {
+            if !self.block_when_paused {
+                return Poll::Ready(Err(TableError::Paused));
+            }
+
+            self.pause.blocked.lock().unwrap().push(cx.waker().clone());
+            // we may have been resumed before our waker was registered
+            if self.pause.paused.load(atomic::Ordering::SeqCst) {
+                return Poll::Pending;
+            }
+        }
+
+        if let Some(max) = self.max_pending {
+            let inflight = &self.inflight;
+            if inflight.count.load(atomic::Ordering::SeqCst) >= max {
+                inflight.blocked.lock().unwrap().push(cx.waker().clone());
+                // a write may have been acknowledged before our waker was registered
+                if inflight.count.load(atomic::Ordering::SeqCst) >= max {
+                    return Poll::Pending;
+                }
+            }
+        }
+
         for s in &mut self.shards {
-            ready!(s.poll_ready(cx)).map_err(TableError::from)?;
+            if let Err(e) = ready!(s.poll_ready(cx)) {
+                return Poll::Ready(Err(TableError::from(e)));
+            }
         }
         Poll::Ready(Ok(()))
     }
@@ -251,11 +689,18 @@ impl Service<Input> for Table {
             None
         };
 
-        i.tracer = self.tracer.take();
+        i.tracer = self
+            .tracer
+            .take()
+            .or_else(|| self.trace_all.map(|tag| (tag, None)));
 
         // TODO: check each row's .len() against self.columns.len() -> WrongColumnCount
 
-        if self.shards.len() == 1 {
+        let inflight = InflightGuard::new(&self.inflight);
+        let metrics = self.metrics.clone();
+        metrics.write_issued(i.data.len());
+        let sent = Instant::now();
+        let fut = if self.shards.len() == 1 {
             let request = Tagged::from(if self.dst_is_local {
                 unsafe { LocalOrNot::for_local_transfer(i) }
             } else {
@@ -279,15 +724,7 @@ impl Service<Input> for Table {
             tracing::trace!("shard request");
             let mut shard_writes = vec![Vec::new(); self.shards.len()];
             for r in i.data.drain(..) {
-                let shard = {
-                    let key = match r {
-                        TableOperation::Insert(ref r) => &r[key_col],
-                        TableOperation::Delete { ref key } => &key[0],
-                        TableOperation::Update { ref key, .. } => &key[0],
-                        TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
-                    };
-                    crate::shard_by(key, self.shards.len())
-                };
+                let shard = self.shard_of(key_col, &r);
                 shard_writes[shard].push(r);
             }
 
@@ -300,6 +737,7 @@ impl Service<Input> for Table {
                                 dst: i.dst,
                                 tracer: i.tracer.clone(),
                                 data: rs,
+                                compress_above: i.compress_above,
                             })
                         }
                     } else {
@@ -307,6 +745,7 @@ impl Service<Input> for Table {
                             dst: i.dst,
                             tracer: i.tracer.clone(),
                             data: rs,
+                            compress_above: i.compress_above,
                         })
                     };
                     let request = Tagged::from(p);
@@ -335,6 +774,26 @@ impl Service<Input> for Table {
                     .map_err(TableError::from)
                     .map_ok(Tagged::from),
             )
+        };
+
+        let timeout = self.timeout;
+        async move {
+            let _inflight = inflight;
+            let res = match timeout {
+                Some(timeout) => {
+                    let deadline = tokio::timer::delay(sent + timeout);
+                    match future::select(Box::pin(fut), Box::pin(deadline)).await {
+                        future::Either::Left((res, _)) => res,
+                        future::Either::Right(_) => Err(TableError::Timeout),
+                    }
+                }
+                None => fut.await,
+            };
+            match res {
+                Ok(_) => metrics.write_acked(sent.elapsed()),
+                Err(ref e) => metrics.write_failed(e),
+            }
+            res
         }
     }
 }
@@ -380,6 +839,136 @@ impl Table {
         self.dst_is_local = true;
     }
 
+    /// Point this handle at a new set of shards for the base table, for example after the
+    /// base's domain has been moved to a different worker.
+    ///
+    /// Writes are sharded across `addrs` from this point on. Note that the new connections are
+    /// dedicated to this handle, and are not shared with other `Table`s.
+    pub fn update_shards(&mut self, addrs: Vec<SocketAddr>) {
+        assert!(!addrs.is_empty(), "base table must have at least one shard");
+        self.shard_addrs = addrs;
+        self.reconnect();
+    }
+
+    /// Replace the connections to each shard of the base table with new ones to the same
+    /// addresses.
+    ///
+    /// A write that finds a connection broken already does this, so this is only needed to
+    /// reconnect proactively, for example after the base's domain is known to have restarted.
+    /// Like with [`Table::update_shards`], the new connections are dedicated to this handle.
+    pub fn reconnect(&mut self) {
+        self.shards = self
+            .shard_addrs
+            .iter()
+            .map(|&addr| table_rpc(addr))
+            .collect();
+    }
+
+    /// Pause writes through this table and all of its clones until [`Table::resume`] is called.
+    ///
+    /// While paused, writes either fail with [`TableError::Paused`], or wait for writes to be
+    /// resumed if [`Table::set_block_when_paused`] is set. Writes that were already sent are not
+    /// affected.
+    pub fn pause(&self) {
+        self.pause.paused.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// Resume writes through this table and all of its clones, waking any writes that are waiting.
+    pub fn resume(&self) {
+        self.pause.paused.store(false, atomic::Ordering::SeqCst);
+        for w in self.pause.blocked.lock().unwrap().drain(..) {
+            w.wake();
+        }
+    }
+
+    /// Returns true if writes through this table are paused.
+    pub fn is_paused(&self) -> bool {
+        self.pause.paused.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Set whether writes through this handle wait for the table to be resumed when it is paused,
+    /// rather than failing with [`TableError::Paused`].
+    pub fn set_block_when_paused(&mut self, block: bool) {
+        self.block_when_paused = block;
+    }
+
+    /// Report events about writes issued through this handle to `sink`.
+    ///
+    /// Clones made after this call report to the same sink. By default, events are discarded.
+    pub fn set_metrics_sink(&mut self, sink: Box<dyn MetricsSink>) {
+        self.metrics = Arc::from(sink);
+    }
+
+    /// Get the number of shards of this base table.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get the shard that `op` would be sent to if it were performed on this table.
+    ///
+    /// Every operation is routed to the single shard that holds its key, so this can be used to
+    /// tell which shards a batch of operations will touch before sending it.
+    pub fn shard_for(&self, op: &TableOperation) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        assert_eq!(self.key.len(), 1, "base sharded by complex key");
+
+        if self.dropped.is_empty() {
+            self.shard_of(self.key[0], op)
+        } else {
+            let mut full = op.clone();
+            self.inject_dropped_cols(&mut full);
+            self.shard_of(self.key[0], &full)
+        }
+    }
+
+    /// Get the number of requests issued through this handle (or any of its clones) that have not
+    /// yet been acknowledged by Noria.
+    pub fn pending_writes(&self) -> usize {
+        self.inflight.count.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Make writes through this handle wait while `max` or more requests issued through it (or any
+    /// of its clones) have not yet been acknowledged, or remove the limit by passing `None`.
+    ///
+    /// This keeps a producer that outpaces Noria from buffering an unbounded number of writes.
+    /// The limit counts requests rather than operations, and is shared by all shards. Note that a
+    /// write stays pending until the future returned for it completes, so callers that issue
+    /// several writes before awaiting them must keep polling the earlier ones.
+    pub fn set_max_pending_writes(&mut self, max: Option<usize>) {
+        assert_ne!(max, Some(0), "at least one write must be allowed");
+        self.max_pending = max;
+    }
+
+    /// Compress writes issued through this handle whose rows serialize to more than `threshold`
+    /// bytes, or disable compression by passing `None`.
+    ///
+    /// This trades CPU time for network bandwidth, and is mostly useful for tables with large text
+    /// columns. Writes to a domain in the same process are never compressed.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compress_above = threshold;
+    }
+
+    /// Fail writes issued through this handle with [`TableError::Timeout`] if they are not
+    /// acknowledged within `timeout`, or wait indefinitely by passing `None` (the default).
+    ///
+    /// Note that a write that times out may still be applied by the base.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Retry up to `retries` times when a write finds the connection to a shard broken, waiting
+    /// `backoff` before the first retry and twice as long before each one after that.
+    ///
+    /// Only failures from before a write is sent are retried; a write that fails after it was sent
+    /// may have been applied, and so is never resent. By default, writes are not retried, though
+    /// the broken connections are still replaced for later writes (see [`Table::reconnect`]).
+    pub fn set_retries(&mut self, retries: usize, backoff: Duration) {
+        self.retries = retries;
+        self.retry_backoff = backoff;
+    }
+
     /// Get the list of columns in this base table.
     ///
     /// Note that this will *not* be updated if the underlying recipe changes and adds or removes
@@ -396,73 +985,59 @@ impl Table {
         self.schema.as_ref()
     }
 
+    /// Get the version of the recipe that was current when this table handle was built.
+    ///
+    /// If this is older than the version returned by `ControllerHandle::schema_version`, the table
+    /// may have been changed by a migration since, and a fresh handle should be obtained from the
+    /// controller to ensure that writes line up with the table's current columns.
+    pub fn schema_version(&self) -> usize {
+        self.schema_version
+    }
+
     fn inject_dropped_cols(&self, r: &mut TableOperation) {
-        use std::mem;
-        let ndropped = self.dropped.len();
-        if ndropped != 0 {
-            // inject defaults for dropped columns
-            let dropped = self.dropped.iter().rev();
-
-            // get a handle to the underlying data vector
-            let r = match *r {
-                TableOperation::Insert(ref mut row)
-                | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
-                _ => unimplemented!("we need to shift the update/delete cols!"),
-            };
+        if self.dropped.is_empty() {
+            return;
+        }
 
-            // we want to be a bit careful here to avoid shifting elements multiple times. we
-            // do this by moving from the back, and swapping the tail element to the end of the
-            // vector until we hit each index.
-
-            // make room in the record
-            r.reserve(ndropped);
-            let mut free = r.len() + ndropped;
-            let mut last_unmoved = r.len() - 1;
-            unsafe { r.set_len(free) };
-            // *technically* we should set all the extra elements just in case we get a panic
-            // below, because otherwise we might call drop() on uninitialized memory. we would
-            // do that like this (note the ::forget() on the swapped-out value):
-            //
-            //   for i in (free - ndropped)..r.len() {
-            //       mem::forget(mem::replace(r.get_mut(i).unwrap(), DataType::None));
-            //   }
-            //
-            // but for efficiency we dont' do that, and just make sure that the code below
-            // doesn't panic. what's the worst that could happen, right?
-
-            // keep trying to insert the next dropped column
-            for (next_insert, default) in dropped {
-                // think of this being at the bottom of the loop
-                // we just hoist it here to avoid underflow if we ever insert at 0
-                free -= 1;
-
-                // shift elements until the next free slot is the one we want to insert into
-                while free > next_insert {
-                    // shift another element so we the free slot is at a lower index
-                    r.swap(last_unmoved, free);
-                    free -= 1;
-
-                    if last_unmoved == 0 {
-                        // avoid underflow
-                        debug_assert_eq!(next_insert, free);
-                        break;
-                    }
-                    last_unmoved -= 1;
+        match *r {
+            TableOperation::Insert(ref mut row)
+            | TableOperation::IdempotentInsert { ref mut row, .. }
+            | TableOperation::InsertIfAbsent { ref mut row } => inject_defaults(row, &self.dropped),
+            TableOperation::InsertOrUpdate {
+                ref mut row,
+                ref mut update,
+            } => {
+                inject_defaults(row, &self.dropped);
+                inject_modifications(update, &self.dropped);
+            }
+            TableOperation::Update { ref mut set, .. } => {
+                inject_modifications(set, &self.dropped);
+            }
+            TableOperation::ConditionalDelete { ref mut expect, .. } => {
+                for (col, _) in expect.iter_mut() {
+                    *col = base_column(*col, &self.dropped);
                 }
-
-                // we're at the right index -- insert the dropped value
-                let current = &mut r[next_insert];
-                let old = mem::replace(current, default.clone());
-                // the old value is uninitialized memory!
-                // (remember how we called set_len above?)
-                mem::forget(old);
-
-                // here, I'll help:
-                // free -= 1;
             }
+            // a key holds only the values of the key columns, not their indices
+            TableOperation::Delete { .. } => {}
         }
     }
 
+    /// Returns the shard that `op`, with defaults for dropped columns injected, is sent to when
+    /// the table is sharded by `key_col`.
+    fn shard_of(&self, key_col: usize, op: &TableOperation) -> usize {
+        let key = match *op {
+            TableOperation::Insert(ref r) => &r[key_col],
+            TableOperation::Delete { ref key } => &key[0],
+            TableOperation::ConditionalDelete { ref key, .. } => &key[0],
+            TableOperation::Update { ref key, .. } => &key[0],
+            TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
+            TableOperation::IdempotentInsert { ref row, .. } => &row[key_col],
+            TableOperation::InsertIfAbsent { ref row } => &row[key_col],
+        };
+        crate::shard_by(key, self.shards.len())
+    }
+
     fn prep_records(&self, mut ops: Vec<TableOperation>) -> Input {
         for r in &mut ops {
             self.inject_dropped_cols(r);
@@ -472,18 +1047,57 @@ impl Table {
             dst: self.node,
             data: ops,
             tracer: None,
+            compress_above: self.compress_above,
         }
     }
 
-    async fn quick_n_dirty<Request, R>(
-        &mut self,
-        r: Request,
-    ) -> Result<R, <Self as Service<Request>>::Error>
+    /// Returns true if writes through this handle have to wait for pending writes to be
+    /// acknowledged, as configured with [`Table::set_max_pending_writes`].
+    fn at_pending_limit(&self) -> bool {
+        self.max_pending
+            .map_or(false, |max| self.pending_writes() >= max)
+    }
+
+    /// Wait until every shard is ready to accept a request.
+    ///
+    /// If a shard's connection has failed, the connections are rebuilt, so that later writes do
+    /// not fail too, and readiness is retried as configured with [`Table::set_retries`]. Since
+    /// nothing has been sent at this point, this cannot apply a write twice.
+    ///
+    /// Only the error that is finally returned is reported to the metrics sink, not the failed
+    /// attempts before it.
+    async fn ready(&mut self) -> Result<(), TableError> {
+        let mut retries = self.retries;
+        let mut backoff = self.retry_backoff;
+        let res = loop {
+            match future::poll_fn(|cx| <Self as Service<Input>>::poll_ready(self, cx)).await {
+                Err(TableError::TransportError(e)) => {
+                    self.reconnect();
+                    if retries == 0 {
+                        break Err(TableError::TransportError(e));
+                    }
+                    retries -= 1;
+
+                    tracing::warn!("retrying write after error: {}", e);
+                    tokio::timer::delay(Instant::now() + backoff).await;
+                    backoff *= 2;
+                }
+                res => break res,
+            }
+        };
+
+        if let Err(ref e) = res {
+            self.metrics.write_failed(e);
+        }
+        res
+    }
+
+    async fn quick_n_dirty<Request, R>(&mut self, r: Request) -> Result<R, TableError>
     where
         Request: Send + 'static,
-        Self: Service<Request, Response = Tagged<R>>,
+        Self: Service<Request, Response = Tagged<R>, Error = TableError>,
     {
-        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.ready().await?;
         Ok(self.call(r).await?.v)
     }
 
@@ -495,65 +1109,545 @@ impl Table {
         self.quick_n_dirty(TableOperation::Insert(u.into())).await
     }
 
-    /// Perform multiple operation on this base table.
-    pub async fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
-    where
-        I: IntoIterator<Item = V>,
-        V: Into<TableOperation>,
-    {
-        self.quick_n_dirty(i.into_iter().map(Into::into).collect::<Vec<_>>())
-            .await
-    }
-
-    /// Delete the row with the given key from this base table.
-    pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
+    /// Insert a single row of data into this base table, after checking that each value matches
+    /// the SQL type of its column.
+    ///
+    /// This is opt-in, since [`Table::insert`] accepts any value in any column. Values are only
+    /// checked for tables that were created from SQL.
+    pub async fn insert_checked<V>(&mut self, u: V) -> Result<(), TableError>
     where
-        I: Into<Vec<DataType>>,
+        V: Into<Vec<DataType>>,
     {
-        self.quick_n_dirty(TableOperation::Delete { key: key.into() })
-            .await
+        let row = u.into();
+        if row.len() != self.columns.len() {
+            return Err(TableError::WrongColumnCount(self.columns.len(), row.len()));
+        }
+        if let Some(ref schema) = self.schema {
+            check_types(&self.columns, schema, &row)?;
+        }
+        self.insert(row).await
     }
 
-    /// Update the row with the given key in this base table.
+    /// Insert a single row of data into this base table, unless a row with the same key already
+    /// exists.
     ///
-    /// `u` is a set of column-modification pairs, where for each pair `(i, m)`, the modification
-    /// `m` will be applied to column `i` of the record with key `key`.
-    pub async fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    /// This differs from [`Table::insert_or_update`] in that an existing row is never modified.
+    /// Note that whether the row was inserted is not reported back.
+    pub async fn insert_if_absent<V>(&mut self, u: V) -> Result<(), TableError>
     where
-        V: IntoIterator<Item = (usize, Modification)>,
+        V: Into<Vec<DataType>>,
     {
         assert!(
             !self.key.is_empty() && self.key_is_primary,
-            "update operations can only be applied to base nodes with key columns"
+            "insert-if-absent can only be applied to base nodes with key columns"
         );
 
-        if key.len() != self.key.len() {
-            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-        }
-
-        let mut set = vec![Modification::None; self.columns.len()];
-        for (coli, m) in u {
-            if coli >= self.columns.len() {
-                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
-            }
-            set[coli] = m;
-        }
-
-        self.quick_n_dirty(TableOperation::Update { key, set })
+        self.quick_n_dirty(TableOperation::InsertIfAbsent { row: u.into() })
             .await
     }
 
-    /// Perform a insert-or-update on this base table.
+    /// Insert a single row of data into this base table, unless an insert with the same `token` was
+    /// recently applied.
     ///
-    /// If a row already exists for the key in `insert`, the existing row will instead be updated
-    /// with the modifications in `u` (as documented in `Table::update`).
-    pub async fn insert_or_update<V>(
-        &mut self,
-        insert: Vec<DataType>,
-        update: V,
-    ) -> Result<(), TableError>
+    /// If the acknowledgement for an insert is lost (e.g., due to a timeout), the insert can be
+    /// retried with the same token without risking a duplicate row, as long as the retry happens
+    /// within the base's idempotency window (see `Base::with_idempotency_window`).
+    pub async fn insert_idempotent<V>(&mut self, u: V, token: u64) -> Result<(), TableError>
     where
-        V: IntoIterator<Item = (usize, Modification)>,
+        V: Into<Vec<DataType>>,
+    {
+        self.quick_n_dirty(TableOperation::IdempotentInsert {
+            row: u.into(),
+            token,
+        })
+        .await
+    }
+
+    /// Insert a copy of the given row into this base table.
+    ///
+    /// Unlike [`Table::insert`], this leaves the caller with ownership of `row`, and clones it
+    /// exactly once.
+    pub async fn insert_ref(&mut self, row: &[DataType]) -> Result<(), TableError> {
+        self.insert(row.to_vec()).await
+    }
+
+    /// Insert a row given as a map from column names to values into this base table.
+    ///
+    /// Columns that are not named take their default value from the table's schema, or NULL if
+    /// the schema does not give one. Columns declared `NOT NULL` without a default, and every
+    /// column of a table that was not created from SQL, must be named.
+    pub async fn insert_named(&mut self, row: HashMap<String, DataType>) -> Result<(), TableError> {
+        let row = named_row(&self.columns, self.schema.as_ref(), row)?;
+        self.insert(row).await
+    }
+
+    /// Perform multiple operation on this base table.
+    ///
+    /// If `i` yields no operations, this returns immediately without contacting Noria.
+    pub async fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let ops = i.into_iter().map(Into::into).collect::<Vec<_>>();
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        self.quick_n_dirty(ops).await
+    }
+
+    /// Insert a batch of rows given in columnar form into this base table.
+    ///
+    /// `data[i]` holds the values of the column named `columns[i]` for every row in the batch, and
+    /// every column of the table must be given exactly once. The batch is transposed into rows
+    /// and sent as a single [`Table::perform_all`].
+    pub async fn insert_columnar(
+        &mut self,
+        columns: &[&str],
+        data: Vec<Vec<DataType>>,
+    ) -> Result<(), TableError> {
+        if columns.len() != self.columns.len() || data.len() != self.columns.len() {
+            return Err(TableError::WrongColumnCount(
+                self.columns.len(),
+                columns.len().max(data.len()),
+            ));
+        }
+
+        // find where each of the given columns goes in the table's rows
+        let mut order = Vec::with_capacity(columns.len());
+        for &name in columns {
+            match self.columns.iter().position(|c| c == name) {
+                Some(coli) if !order.contains(&coli) => order.push(coli),
+                _ => return Err(TableError::UnknownColumn(name.to_owned())),
+            }
+        }
+
+        let nrows = data.first().map(Vec::len).unwrap_or(0);
+        if let Some(col) = data.iter().find(|col| col.len() != nrows) {
+            return Err(TableError::ColumnLengthMismatch(nrows, col.len()));
+        }
+
+        let ncols = self.columns.len();
+        let mut values: Vec<_> = data.into_iter().map(Vec::into_iter).collect();
+        let ops: Vec<_> = (0..nrows)
+            .map(|_| {
+                let mut row = vec![DataType::None; ncols];
+                for (vals, &coli) in values.iter_mut().zip(&order) {
+                    row[coli] = vals.next().unwrap();
+                }
+                TableOperation::Insert(row)
+            })
+            .collect();
+
+        self.perform_all(ops).await
+    }
+
+    /// Check that `op` has the right number of columns for this table.
+    fn validate(&self, op: &TableOperation) -> Result<(), TableError> {
+        let ncols = self.columns.len();
+        let check_row = |row: &[DataType]| {
+            if row.len() != ncols {
+                return Err(TableError::WrongColumnCount(ncols, row.len()));
+            }
+            Ok(())
+        };
+        let check_key = |key: &[DataType]| {
+            if key.len() != self.key.len() {
+                return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+            }
+            Ok(())
+        };
+
+        match *op {
+            TableOperation::Insert(ref row)
+            | TableOperation::IdempotentInsert { ref row, .. }
+            | TableOperation::InsertIfAbsent { ref row } => check_row(row),
+            TableOperation::Delete { ref key } => check_key(key),
+            TableOperation::ConditionalDelete {
+                ref key,
+                ref expect,
+            } => {
+                check_key(key)?;
+                check_expect(expect, ncols)
+            }
+            TableOperation::Update { ref key, ref set } => {
+                check_key(key)?;
+                if set.len() > ncols {
+                    return Err(TableError::WrongColumnCount(ncols, set.len()));
+                }
+                check_modifications(set, ncols, false)
+            }
+            TableOperation::InsertOrUpdate {
+                ref row,
+                ref update,
+            } => {
+                check_row(row)?;
+                if update.len() > ncols {
+                    return Err(TableError::WrongColumnCount(ncols, update.len()));
+                }
+                check_modifications(update, ncols, true)
+            }
+        }
+    }
+
+    /// Perform a batch of operations of any kind on this base table in a single request.
+    ///
+    /// Unlike [`Table::perform_all`], each operation is first checked to have the right number of
+    /// columns (or key columns) for this table, and nothing is sent if any of them do not.
+    pub async fn submit(&mut self, ops: Vec<TableOperation>) -> Result<(), TableError> {
+        for op in &ops {
+            self.validate(op)?;
+        }
+        self.perform_all(ops).await
+    }
+
+    /// Perform the operations in `i` that are valid for this base table in a single request,
+    /// skipping the rest.
+    ///
+    /// Operations are checked like with [`Table::submit`]. Once the valid operations have been
+    /// applied, this returns the position in `i` of each skipped operation along with why it was
+    /// skipped. This suits bulk imports that should log bad rows rather than abort.
+    pub async fn perform_all_valid<I, V>(
+        &mut self,
+        i: I,
+    ) -> Result<Vec<(usize, TableError)>, TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let mut ops = Vec::new();
+        let mut skipped = Vec::new();
+        for (n, op) in i.into_iter().map(Into::into).enumerate() {
+            match self.validate(&op) {
+                Ok(()) => ops.push(op),
+                Err(e) => skipped.push((n, e)),
+            }
+        }
+
+        self.perform_all(ops).await?;
+        Ok(skipped)
+    }
+
+    /// Perform multiple operations on this base table, calling `on_progress` with the number of
+    /// operations sent so far after every `every` operations.
+    ///
+    /// The operations are sent in batches of `every` without waiting for earlier batches to
+    /// complete, and this only returns once all of them have been acknowledged. This is useful for
+    /// reporting progress during large imports.
+    pub async fn perform_all_with_progress<I, V, F>(
+        &mut self,
+        i: I,
+        every: usize,
+        mut on_progress: F,
+    ) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+        F: FnMut(usize),
+    {
+        assert!(every > 0, "cannot report progress every 0 operations");
+
+        let mut ops = i.into_iter().map(Into::into);
+        let mut sent = 0;
+        let mut wait_for = FuturesUnordered::new();
+        loop {
+            let batch: Vec<TableOperation> = ops.by_ref().take(every).collect();
+            if batch.is_empty() {
+                break;
+            }
+
+            sent += batch.len();
+            while self.at_pending_limit() {
+                match wait_for.next().await {
+                    Some(res) => res?,
+                    None => break,
+                };
+            }
+            self.ready().await?;
+            wait_for.push(self.call(batch));
+            on_progress(sent);
+        }
+
+        wait_for.try_for_each(|_| async { Ok(()) }).await
+    }
+
+    /// Perform multiple operations on this base table, such that a failed write can be retried
+    /// without repeating the operations that were already applied.
+    ///
+    /// The operations are sent to each shard of the table as a separate request. If any of those
+    /// requests fail, the first error is returned along with the operations of every failed
+    /// request. The operations of the other shards have been applied, so only the returned
+    /// operations need to be retried.
+    pub async fn perform_all_resumable<I, V>(
+        &mut self,
+        i: I,
+    ) -> Result<(), (TableError, Vec<TableOperation>)>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let mut shard_ops = vec![Vec::new(); self.shards.len()];
+        for op in i.into_iter().map(Into::into) {
+            let shard = self.shard_for(&op);
+            shard_ops[shard].push(op);
+        }
+
+        let mut failed: Option<(TableError, Vec<TableOperation>)> = None;
+        let mut wait_for = FuturesUnordered::new();
+        for ops in shard_ops.into_iter().filter(|ops| !ops.is_empty()) {
+            while self.at_pending_limit() {
+                match wait_for.next().await {
+                    Some(Err((e, ops))) => match failed {
+                        Some((_, ref mut retry)) => retry.extend(ops),
+                        None => failed = Some((e, ops)),
+                    },
+                    Some(Ok(_)) => {}
+                    None => break,
+                }
+            }
+            match self.ready().await {
+                Ok(()) => {
+                    let write = self.call(ops.clone());
+                    wait_for.push(async move { write.await.map_err(|e| (e, ops)) });
+                }
+                Err(e) => match failed {
+                    Some((_, ref mut retry)) => retry.extend(ops),
+                    None => failed = Some((e, ops)),
+                },
+            }
+        }
+
+        while let Some(res) = wait_for.next().await {
+            if let Err((e, ops)) = res {
+                match failed {
+                    Some((_, ref mut retry)) => retry.extend(ops),
+                    None => failed = Some((e, ops)),
+                }
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Delete the row with the given key from this base table, but only if each column in
+    /// `expected` currently holds the given value.
+    ///
+    /// This gives compare-and-delete semantics: a row that has been changed since `expected` was
+    /// read is left in place. Note that whether the row was deleted is not reported back.
+    pub async fn delete_if<I, V>(&mut self, key: I, expected: V) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+        V: IntoIterator<Item = (usize, DataType)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "conditional deletes can only be applied to base nodes with key columns"
+        );
+
+        let key = key.into();
+        if key.len() != self.key.len() {
+            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+        }
+
+        let expect: Vec<_> = expected.into_iter().collect();
+        check_expect(&expect, self.columns.len())?;
+
+        self.quick_n_dirty(TableOperation::ConditionalDelete { key, expect })
+            .await
+    }
+
+    /// Perform multiple operations on this base table, first collapsing operations in the batch
+    /// that cancel each other out.
+    ///
+    /// Operations are collapsed per key as follows:
+    ///
+    ///  - an insert followed by a delete of the same key is dropped, leaving only the delete, so
+    ///    that a row already present in the base is still deleted.
+    ///  - a delete followed by an insert of the same key become a single insert-or-update that
+    ///    sets every column to the inserted row.
+    ///
+    /// Only the most recent operation on each key is considered, so earlier inserts of the same
+    /// key are kept and still removed by the delete. The resulting state of the base is the same
+    /// as if the batch had been performed as given.
+    ///
+    /// Since this changes which operations the base observes, it is opt-in, and is only applied to
+    /// tables with a primary key. Other tables behave as with [`Table::perform_all`].
+    pub async fn perform_all_collapsed<I, V>(&mut self, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let ops = i.into_iter().map(Into::into).collect::<Vec<_>>();
+        // the key holds base columns, but rows are still missing any dropped columns
+        let key: Option<Vec<usize>> = self
+            .key
+            .iter()
+            .map(|&kc| (0..self.columns.len()).find(|&c| base_column(c, &self.dropped) == kc))
+            .collect();
+        let ops = match key {
+            Some(ref key) if !key.is_empty() && self.key_is_primary => collapse(key, ops),
+            _ => ops,
+        };
+        self.perform_all(ops).await
+    }
+
+    /// Delete the row with the given key from this base table.
+    pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+    {
+        self.quick_n_dirty(TableOperation::Delete { key: key.into() })
+            .await
+    }
+
+    /// Delete the rows with the given keys from this base table in a single request.
+    ///
+    /// Each key is first checked to have the right number of columns, and nothing is sent if any
+    /// of them do not. The deletes are still routed to whichever shard holds each key.
+    pub async fn delete_many<I>(&mut self, keys: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = Vec<DataType>>,
+    {
+        let nkey = self.key.len();
+        let ops = keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, key)| {
+                if key.len() != nkey {
+                    return Err(TableError::WrongKeyColumnCountAt(i, nkey, key.len()));
+                }
+                Ok(TableOperation::Delete { key })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.perform_all(ops).await
+    }
+
+    /// Delete the rows with the given keys from this base table in chunks of `chunk_size`,
+    /// calling `on_progress` with the number of deletes performed so far after each chunk.
+    ///
+    /// Each chunk is acknowledged by Noria before the next is sent, so a large purge does not
+    /// flood the base's domain. The base cannot be asked to enumerate rows over this connection,
+    /// so the keys to delete are usually found by querying a view over the base.
+    pub async fn delete_chunked<I, F>(
+        &mut self,
+        keys: I,
+        chunk_size: usize,
+        mut on_progress: F,
+    ) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = Vec<DataType>>,
+        F: FnMut(usize),
+    {
+        assert!(chunk_size > 0, "chunks must hold at least one delete");
+
+        let mut keys = keys.into_iter();
+        let mut deleted = 0;
+        loop {
+            let chunk: Vec<_> = keys
+                .by_ref()
+                .take(chunk_size)
+                .map(|key| TableOperation::Delete { key })
+                .collect();
+            if chunk.is_empty() {
+                return Ok(());
+            }
+
+            deleted += chunk.len();
+            self.perform_all(chunk).await?;
+            on_progress(deleted);
+        }
+    }
+
+    /// Update the row with the given key in this base table.
+    ///
+    /// `u` is a set of column-modification pairs, where for each pair `(i, m)`, the modification
+    /// `m` will be applied to column `i` of the record with key `key`.
+    pub async fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "update operations can only be applied to base nodes with key columns"
+        );
+
+        if key.len() != self.key.len() {
+            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+        }
+
+        let mut set = vec![Modification::None; self.columns.len()];
+        for (coli, m) in u {
+            if coli >= self.columns.len() {
+                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+            }
+            set[coli] = m;
+        }
+        check_modifications(&set, self.columns.len(), false)?;
+
+        self.quick_n_dirty(TableOperation::Update { key, set })
+            .await
+    }
+
+    /// Set column `col` of the row with the given key in this base table to `value`.
+    ///
+    /// This is a shorthand for a [`Table::update`] that modifies a single column.
+    pub async fn set<V>(
+        &mut self,
+        key: Vec<DataType>,
+        col: usize,
+        value: V,
+    ) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        self.update(key, vec![(col, Modification::set(value))])
+            .await
+    }
+
+    /// Add `by` to column `col` of the row with the given key in this base table.
+    ///
+    /// This is a shorthand for a [`Table::update`] that applies [`Operation::Add`] to a single
+    /// column, as is common for counters.
+    pub async fn increment<V>(
+        &mut self,
+        key: Vec<DataType>,
+        col: usize,
+        by: V,
+    ) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        self.update(key, vec![(col, Modification::apply(Operation::Add, by))])
+            .await
+    }
+
+    /// Subtract `by` from column `col` of the row with the given key in this base table.
+    ///
+    /// See [`Table::increment`].
+    pub async fn decrement<V>(
+        &mut self,
+        key: Vec<DataType>,
+        col: usize,
+        by: V,
+    ) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        self.update(key, vec![(col, Modification::apply(Operation::Sub, by))])
+            .await
+    }
+
+    /// Perform a insert-or-update on this base table.
+    ///
+    /// If a row already exists for the key in `insert`, the existing row will instead be updated
+    /// with the modifications in `u` (as documented in `Table::update`).
+    pub async fn insert_or_update<V>(
+        &mut self,
+        insert: Vec<DataType>,
+        update: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
     {
         assert!(
             !self.key.is_empty() && self.key_is_primary,
@@ -574,6 +1668,7 @@ impl Table {
             }
             set[coli] = m;
         }
+        check_modifications(&set, self.columns.len(), true)?;
 
         self.quick_n_dirty(TableOperation::InsertOrUpdate {
             row: insert,
@@ -594,12 +1689,75 @@ impl Table {
         self.tracer = Some((tag, None));
     }
 
+    /// Trace every modification to this base table until [`Table::stop_tracing`] is called.
+    ///
+    /// Events are delivered as described for [`Table::trace_next`], and are all tagged with the
+    /// given `tag`. A tag set with `trace_next` takes precedence for the next modification.
+    pub fn start_tracing(&mut self, tag: u64) {
+        self.trace_all = Some(tag);
+    }
+
+    /// Stop tracing modifications started with [`Table::start_tracing`].
+    pub fn stop_tracing(&mut self) {
+        self.trace_all = None;
+    }
+
     /// Switch to a synchronous interface for this table.
     pub fn into_sync(self) -> SyncTable {
         SyncTable(self)
     }
 }
 
+/// Collapse operations in `ops` that cancel each other out, as described in
+/// [`Table::perform_all_collapsed`].
+fn collapse(key: &[usize], ops: Vec<TableOperation>) -> Vec<TableOperation> {
+    let mut out: Vec<Option<TableOperation>> = Vec::with_capacity(ops.len());
+    let mut last: HashMap<Vec<DataType>, usize> = HashMap::new();
+    for op in ops {
+        let k: Option<Vec<DataType>> = match op {
+            TableOperation::Insert(ref row)
+            | TableOperation::InsertOrUpdate { ref row, .. }
+            | TableOperation::IdempotentInsert { ref row, .. }
+            | TableOperation::InsertIfAbsent { ref row } => {
+                key.iter().map(|&c| row.get(c).cloned()).collect()
+            }
+            TableOperation::Delete { ref key }
+            | TableOperation::ConditionalDelete { ref key, .. }
+            | TableOperation::Update { ref key, .. } => Some(key.clone()),
+        };
+        let k = match k {
+            Some(k) => k,
+            None => {
+                // too short to hold the key; leave it for validation to reject
+                out.push(Some(op));
+                continue;
+            }
+        };
+
+        if let Some(&j) = last.get(&k) {
+            match (&out[j], &op) {
+                (Some(TableOperation::Insert(_)), TableOperation::Delete { .. }) => {
+                    // the delete must still go through in case the key was already present
+                    out[j] = None;
+                }
+                (Some(TableOperation::Delete { .. }), TableOperation::Insert(row)) => {
+                    let update = row.iter().cloned().map(Modification::Set).collect();
+                    out[j] = Some(TableOperation::InsertOrUpdate {
+                        row: row.clone(),
+                        update,
+                    });
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        last.insert(k, out.len());
+        out.push(Some(op));
+    }
+    out.into_iter().flatten().collect()
+}
+
 /// A synchronous wrapper around [`Table`] where all methods block (using `wait`) for the operation
 /// to complete before returning.
 #[derive(Clone, Debug)]
@@ -640,6 +1798,40 @@ impl SyncTable {
         sync!(self.insert(u))
     }
 
+    /// See [`Table::insert_checked`].
+    pub fn insert_checked<V>(&mut self, u: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        sync!(self.insert_checked(u))
+    }
+
+    /// See [`Table::insert_if_absent`].
+    pub fn insert_if_absent<V>(&mut self, u: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        sync!(self.insert_if_absent(u))
+    }
+
+    /// See [`Table::insert_idempotent`].
+    pub fn insert_idempotent<V>(&mut self, u: V, token: u64) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        sync!(self.insert_idempotent(u, token))
+    }
+
+    /// See [`Table::insert_ref`].
+    pub fn insert_ref(&mut self, row: &[DataType]) -> Result<(), TableError> {
+        sync!(self.insert_ref(row))
+    }
+
+    /// See [`Table::insert_named`].
+    pub fn insert_named(&mut self, row: HashMap<String, DataType>) -> Result<(), TableError> {
+        sync!(self.insert_named(row))
+    }
+
     /// See [`Table::perform_all`].
     pub fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
     where
@@ -649,6 +1841,24 @@ impl SyncTable {
         sync!(self.perform_all(i))
     }
 
+    /// See [`Table::perform_all_collapsed`].
+    pub fn perform_all_collapsed<I, V>(&mut self, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        sync!(self.perform_all_collapsed(i))
+    }
+
+    /// See [`Table::insert_columnar`].
+    pub fn insert_columnar(
+        &mut self,
+        columns: &[&str],
+        data: Vec<Vec<DataType>>,
+    ) -> Result<(), TableError> {
+        sync!(self.insert_columnar(columns, data))
+    }
+
     /// See [`Table::delete`].
     pub fn delete<I>(&mut self, key: I) -> Result<(), TableError>
     where
@@ -657,6 +1867,78 @@ impl SyncTable {
         sync!(self.delete(key))
     }
 
+    /// See [`Table::submit`].
+    pub fn submit(&mut self, ops: Vec<TableOperation>) -> Result<(), TableError> {
+        sync!(self.submit(ops))
+    }
+
+    /// See [`Table::perform_all_valid`].
+    pub fn perform_all_valid<I, V>(&mut self, i: I) -> Result<Vec<(usize, TableError)>, TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        sync!(self.perform_all_valid(i))
+    }
+
+    /// See [`Table::perform_all_resumable`].
+    pub fn perform_all_resumable<I, V>(
+        &mut self,
+        i: I,
+    ) -> Result<(), (TableError, Vec<TableOperation>)>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        sync!(self.perform_all_resumable(i))
+    }
+
+    /// See [`Table::perform_all_with_progress`].
+    pub fn perform_all_with_progress<I, V, F>(
+        &mut self,
+        i: I,
+        every: usize,
+        on_progress: F,
+    ) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+        F: FnMut(usize),
+    {
+        sync!(self.perform_all_with_progress(i, every, on_progress))
+    }
+
+    /// See [`Table::delete_if`].
+    pub fn delete_if<I, V>(&mut self, key: I, expected: V) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+        V: IntoIterator<Item = (usize, DataType)>,
+    {
+        sync!(self.delete_if(key, expected))
+    }
+
+    /// See [`Table::delete_many`].
+    pub fn delete_many<I>(&mut self, keys: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = Vec<DataType>>,
+    {
+        sync!(self.delete_many(keys))
+    }
+
+    /// See [`Table::delete_chunked`].
+    pub fn delete_chunked<I, F>(
+        &mut self,
+        keys: I,
+        chunk_size: usize,
+        on_progress: F,
+    ) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = Vec<DataType>>,
+        F: FnMut(usize),
+    {
+        sync!(self.delete_chunked(keys, chunk_size, on_progress))
+    }
+
     /// See [`Table::update`].
     pub fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
     where
@@ -665,6 +1947,30 @@ impl SyncTable {
         sync!(self.update(key, u))
     }
 
+    /// See [`Table::set`].
+    pub fn set<V>(&mut self, key: Vec<DataType>, col: usize, value: V) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        sync!(self.set(key, col, value))
+    }
+
+    /// See [`Table::increment`].
+    pub fn increment<V>(&mut self, key: Vec<DataType>, col: usize, by: V) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        sync!(self.increment(key, col, by))
+    }
+
+    /// See [`Table::decrement`].
+    pub fn decrement<V>(&mut self, key: Vec<DataType>, col: usize, by: V) -> Result<(), TableError>
+    where
+        V: Into<DataType>,
+    {
+        sync!(self.decrement(key, col, by))
+    }
+
     /// See [`Table::insert_or_update`].
     pub fn insert_or_update<V>(
         &mut self,
@@ -682,3 +1988,241 @@ impl SyncTable {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(compress_above: Option<usize>) -> Input {
+        let row: Vec<DataType> = (0..100)
+            .map(|_| "a fairly long text value".into())
+            .collect();
+        Input {
+            dst: unsafe { LocalNodeIndex::make(0) },
+            data: vec![TableOperation::Insert(row); 10],
+            tracer: None,
+            compress_above,
+        }
+    }
+
+    #[test]
+    fn input_compression_roundtrip() {
+        let plain = bincode::serialize(&input(None)).unwrap();
+        let compressed = bincode::serialize(&input(Some(0))).unwrap();
+        assert!(compressed.len() < plain.len());
+
+        let plain: Input = bincode::deserialize(&plain).unwrap();
+        let compressed: Input = bincode::deserialize(&compressed).unwrap();
+        assert_eq!(plain.data, input(None).data);
+        assert_eq!(compressed.data, plain.data);
+    }
+
+    #[test]
+    fn inserted_modifications() {
+        let set = vec![Modification::None, Modification::FromInserted(0)];
+        assert!(check_modifications(&set, 2, true).is_ok());
+        match check_modifications(&set, 2, false) {
+            Err(TableError::NoInsertedRow(1)) => {}
+            r => panic!("expected no inserted row for column 1, got {:?}", r),
+        }
+
+        let set = vec![
+            Modification::None,
+            Modification::ApplyInserted(Operation::Add, 2),
+        ];
+        match check_modifications(&set, 2, true) {
+            Err(TableError::WrongColumnCount(2, 3)) => {}
+            r => panic!("expected a wrong column count, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn expected_columns() {
+        assert!(check_expect(&[(0, 1.into()), (1, "a".into())], 2).is_ok());
+        match check_expect(&[(0, 1.into()), (2, "a".into())], 2) {
+            Err(TableError::WrongColumnCount(2, 3)) => {}
+            r => panic!("expected a wrong column count, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn inject_dropped_modifications() {
+        let mut dropped = VecMap::new();
+        dropped.insert(1, DataType::from("b"));
+        dropped.insert(3, DataType::from("d"));
+
+        assert_eq!(base_column(0, &dropped), 0);
+        assert_eq!(base_column(1, &dropped), 2);
+        assert_eq!(base_column(2, &dropped), 4);
+
+        let mut set = vec![
+            Modification::None,
+            Modification::set(1),
+            Modification::FromInserted(2),
+        ];
+        inject_modifications(&mut set, &dropped);
+        assert_eq!(
+            set,
+            vec![
+                Modification::None,
+                Modification::None,
+                Modification::set(1),
+                Modification::None,
+                Modification::FromInserted(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn inject_dropped_defaults() {
+        let mut dropped = VecMap::new();
+        dropped.insert(0, DataType::from("first"));
+        dropped.insert(2, DataType::from("middle"));
+        dropped.insert(5, DataType::from("last"));
+
+        let mut row = vec![1.into(), 2.into(), 3.into()];
+        inject_defaults(&mut row, &dropped);
+        assert_eq!(
+            row,
+            vec![
+                "first".into(),
+                1.into(),
+                "middle".into(),
+                2.into(),
+                3.into(),
+                "last".into()
+            ]
+        );
+
+        let mut row = Vec::new();
+        let mut first = VecMap::new();
+        first.insert(0, DataType::from(42));
+        inject_defaults(&mut row, &first);
+        assert_eq!(row, vec![42.into()]);
+    }
+
+    #[test]
+    fn named_row_defaults() {
+        let schema = match nom_sql::parse_query(
+            "CREATE TABLE t (a int NOT NULL, b int, c int DEFAULT 7, d int NOT NULL DEFAULT 8);",
+        )
+        .unwrap()
+        {
+            nom_sql::SqlQuery::CreateTable(schema) => schema,
+            _ => unreachable!(),
+        };
+        let columns: Vec<String> = vec!["a".into(), "b".into(), "c".into(), "d".into()];
+        let row = |values: &[(&str, i32)]| -> HashMap<String, DataType> {
+            values
+                .iter()
+                .map(|&(name, v)| (name.to_owned(), v.into()))
+                .collect()
+        };
+
+        assert_eq!(
+            named_row(&columns, Some(&schema), row(&[("a", 1)])).unwrap(),
+            vec![1.into(), DataType::None, 7.into(), 8.into()]
+        );
+        assert_eq!(
+            named_row(
+                &columns,
+                Some(&schema),
+                row(&[("c", 3), ("a", 1), ("d", 4)])
+            )
+            .unwrap(),
+            vec![1.into(), DataType::None, 3.into(), 4.into()]
+        );
+        match named_row(&columns, Some(&schema), row(&[("b", 2)])) {
+            Err(TableError::MissingColumn(ref c)) if c == "a" => {}
+            r => panic!("expected missing column a, got {:?}", r),
+        }
+        match named_row(&columns, Some(&schema), row(&[("a", 1), ("e", 5)])) {
+            Err(TableError::UnknownColumn(ref c)) if c == "e" => {}
+            r => panic!("expected unknown column e, got {:?}", r),
+        }
+        match named_row(&columns, None, row(&[("a", 1)])) {
+            Err(TableError::MissingColumn(_)) => {}
+            r => panic!("expected missing column, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn check_row_types() {
+        let schema =
+            match nom_sql::parse_query("CREATE TABLE t (a int NOT NULL, b varchar(255), c int);")
+                .unwrap()
+            {
+                nom_sql::SqlQuery::CreateTable(schema) => schema,
+                _ => unreachable!(),
+            };
+        let columns: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+
+        assert!(check_types(&columns, &schema, &[1.into(), "x".into(), 2.into()]).is_ok());
+        assert!(check_types(
+            &columns,
+            &schema,
+            &[1.into(), DataType::None, DataType::None]
+        )
+        .is_ok());
+        match check_types(&columns, &schema, &[1.into(), "x".into(), "y".into()]) {
+            Err(TableError::TypeMismatch { ref column, .. }) if column == "c" => {}
+            r => panic!("expected type mismatch in c, got {:?}", r),
+        }
+        match check_types(&columns, &schema, &[DataType::None, "x".into(), 2.into()]) {
+            Err(TableError::TypeMismatch { ref column, .. }) if column == "a" => {}
+            r => panic!("expected type mismatch in a, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn collapse_batch() {
+        let insert = |k: i32, v: &str| TableOperation::Insert(vec![k.into(), v.into()]);
+        let delete = |k: i32| TableOperation::Delete {
+            key: vec![k.into()],
+        };
+
+        // the delete is kept in case the key was already in the base
+        assert_eq!(
+            collapse(&[0], vec![insert(1, "a"), insert(2, "b"), delete(1)]),
+            vec![insert(2, "b"), delete(1)]
+        );
+        // earlier inserts of the same key are never dropped
+        assert_eq!(
+            collapse(&[0], vec![insert(1, "a"), insert(1, "b"), delete(1)]),
+            vec![insert(1, "a"), delete(1)]
+        );
+        assert_eq!(
+            collapse(&[0], vec![delete(1), insert(1, "a"), delete(2)]),
+            vec![
+                TableOperation::InsertOrUpdate {
+                    row: vec![1.into(), "a".into()],
+                    update: vec![Modification::Set(1.into()), Modification::Set("a".into())],
+                },
+                delete(2),
+            ]
+        );
+        // an insert after a collapsed pair replaces the remaining delete
+        assert_eq!(
+            collapse(&[0], vec![insert(1, "a"), delete(1), insert(1, "b")]),
+            vec![TableOperation::InsertOrUpdate {
+                row: vec![1.into(), "b".into()],
+                update: vec![Modification::Set(1.into()), Modification::Set("b".into())],
+            }]
+        );
+        // rows too short to hold the key are left alone
+        assert_eq!(
+            collapse(
+                &[1],
+                vec![TableOperation::Insert(vec![1.into()]), delete(1)]
+            ),
+            vec![TableOperation::Insert(vec![1.into()]), delete(1)]
+        );
+    }
+
+    #[test]
+    fn input_compression_threshold() {
+        let plain = bincode::serialize(&input(None)).unwrap();
+        let above = bincode::serialize(&input(Some(usize::max_value()))).unwrap();
+        assert_eq!(plain, above);
+    }
+}